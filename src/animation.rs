@@ -0,0 +1,260 @@
+use tiny_skia::{IntRect, Pixmap, Transform};
+
+use crate::draw::render_stroke_transformed;
+use crate::types::{Rect, Stroke};
+
+/// Time between animation frames, i.e. 25 frames per second.
+const FRAME_MS: u32 = 40;
+/// Pauses between strokes are shortened to at most this long, so a drawing that took a
+/// whole meeting still animates in a reasonable time.
+const MAX_PAUSE_MS: u32 = 500;
+/// Strokes are likewise drawn in at most this long; the rest of one held down for minutes
+/// appears at once.
+const MAX_STROKE_MS: u32 = 10_000;
+/// How long the finished drawing stays up before the animation loops.
+const HOLD_MS: u32 = 3000;
+
+/// A rectangle of updated pixels and how long it stays on screen.
+struct Frame {
+    rect: IntRect,
+    /// Straight (not premultiplied) RGBA, as PNG expects.
+    rgba: Vec<u8>,
+    delay_ms: u32,
+}
+
+/// Renders the strokes being drawn, in order and at the pace they were drawn in, as a
+/// looping APNG on top of `base`. `transform` maps the strokes' coordinates onto `base`,
+/// which may be a higher-resolution screenshot of an output.
+///
+/// Only the first frame covers the whole image; every later one is just the region that
+/// changed, so long animations of a 4K output stay small.
+pub fn encode_apng(
+    base: &Pixmap,
+    strokes: &[Stroke],
+    transform: Transform,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let bounds = IntRect::from_xywh(0, 0, base.width(), base.height()).ok_or("empty image")?;
+    let starts = schedule(strokes);
+    let end = strokes
+        .iter()
+        .zip(&starts)
+        .map(|(stroke, start)| start.saturating_add(duration(stroke)))
+        .max()
+        .unwrap_or(0);
+
+    let mut frames = vec![Frame {
+        rect: bounds,
+        rgba: demultiply(base),
+        delay_ms: 0,
+    }];
+    let mut done = base.clone();
+    let mut next = 0;
+    // How many points of stroke `next` the previous frame showed
+    let mut shown = 0;
+    let mut t = 0;
+    loop {
+        let mut damage: Option<Rect> = None;
+        let mut add_damage = |rect: Option<Rect>| {
+            if let Some(rect) = rect {
+                damage = Some(damage.as_ref().map_or(rect.clone(), |d| d.union(&rect)));
+            }
+        };
+
+        // Strokes finished by now are drawn for good
+        while next < strokes.len() && starts[next].saturating_add(duration(&strokes[next])) <= t {
+            render_stroke_transformed(&mut done.as_mut(), &strokes[next], transform);
+            add_damage(strokes[next].bounding_box());
+            next += 1;
+            shown = 0;
+        }
+        // Of the stroke being drawn, show the points made so far. Shapes only have their
+        // first and last point, so they simply appear once finished.
+        let partial = strokes
+            .get(next)
+            .filter(|stroke| !stroke.tool.is_shape() && starts[next] <= t)
+            .map(|stroke| {
+                let drawn = stroke
+                    .times
+                    .iter()
+                    .take_while(|&&time| {
+                        starts[next].saturating_add(time.saturating_sub(stroke.times[0])) <= t
+                    })
+                    .count();
+                Stroke {
+                    points: stroke.points[..drawn].to_vec(),
+                    times: stroke.times[..drawn].to_vec(),
+                    ..stroke.clone()
+                }
+            })
+            .filter(|stroke| stroke.points.len() > 1);
+        // The partial stroke only ever grows, so its box covers last frame's as well
+        if let Some(stroke) = partial.as_ref().filter(|s| s.points.len() != shown) {
+            shown = stroke.points.len();
+            add_damage(stroke.bounding_box());
+        }
+
+        let region = damage
+            .and_then(|rect| device_rect(&rect, transform))
+            .and_then(|rect| rect.intersect(&bounds));
+        match region {
+            Some(rect) => {
+                let mut pixmap = done.clone_rect(rect).ok_or("invalid frame region")?;
+                if let Some(stroke) = &partial {
+                    let offset = transform.post_translate(-rect.x() as f32, -rect.y() as f32);
+                    render_stroke_transformed(&mut pixmap.as_mut(), stroke, offset);
+                }
+                frames.push(Frame {
+                    rect,
+                    rgba: demultiply(&pixmap),
+                    delay_ms: FRAME_MS,
+                });
+            }
+            // Nothing new on screen, so the previous frame just stays up longer
+            None => frames.last_mut().unwrap().delay_ms += FRAME_MS,
+        }
+
+        if t >= end {
+            break;
+        }
+        t = t.saturating_add(FRAME_MS);
+    }
+    frames.last_mut().unwrap().delay_ms = HOLD_MS;
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, base.width(), base.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Zero plays loops forever
+    encoder.set_animated(frames.len() as u32, 0)?;
+    let mut writer = encoder.write_header()?;
+    for frame in &frames {
+        writer.set_frame_delay(frame.delay_ms.min(u16::MAX as u32) as u16, 1000)?;
+        // Dimension and position are each checked against the other, so go via the origin
+        writer.set_frame_position(0, 0)?;
+        writer.set_frame_dimension(frame.rect.width(), frame.rect.height())?;
+        writer.set_frame_position(frame.rect.x() as u32, frame.rect.y() as u32)?;
+        writer.set_blend_op(png::BlendOp::Source)?;
+        writer.set_dispose_op(png::DisposeOp::None)?;
+        writer.write_image_data(&frame.rgba)?;
+    }
+    writer.finish()?;
+    Ok(out)
+}
+
+/// When each stroke starts on the animation's timeline: strokes follow each other with
+/// the pauses between them capped at `MAX_PAUSE_MS`. Strokes without timestamps (e.g.
+/// from older sessions) are drawn instantly, one pause apart.
+fn schedule(strokes: &[Stroke]) -> Vec<u32> {
+    let mut starts = Vec::with_capacity(strokes.len());
+    let mut t: u32 = 0;
+    let mut previous_end: Option<u32> = None;
+    for stroke in strokes {
+        if let Some(previous_end) = previous_end {
+            // Redone strokes can predate the stroke before them; wrapping makes that a
+            // huge pause, which gets capped like any other long one
+            let pause = match stroke.times.first() {
+                Some(first) => first.wrapping_sub(previous_end),
+                None => MAX_PAUSE_MS,
+            };
+            t = t.saturating_add(pause.min(MAX_PAUSE_MS));
+        }
+        starts.push(t);
+        t = t.saturating_add(duration(stroke));
+        previous_end = stroke.times.last().copied();
+    }
+    starts
+}
+
+/// How long a stroke takes to draw in the animation, in milliseconds: the time it took,
+/// capped at `MAX_STROKE_MS`.
+fn duration(stroke: &Stroke) -> u32 {
+    match (stroke.times.first(), stroke.times.last()) {
+        (Some(first), Some(last)) => last.saturating_sub(*first).min(MAX_STROKE_MS),
+        _ => 0,
+    }
+}
+
+/// The pixels covered by a surface rectangle once transformed onto the image.
+fn device_rect(rect: &Rect, transform: Transform) -> Option<IntRect> {
+    tiny_skia::Rect::from_xywh(rect.x as f32, rect.y as f32, rect.w as f32, rect.h as f32)?
+        .transform(transform)?
+        .round_out()
+}
+
+fn demultiply(pixmap: &Pixmap) -> Vec<u8> {
+    pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let c = pixel.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Point, Tool};
+
+    fn stroke(points: &[(f32, f32)], times: &[u32]) -> Stroke {
+        Stroke {
+            tool: Tool::Pen,
+            points: points.iter().map(|&(x, y)| Point { x, y }).collect(),
+            times: times.to_vec(),
+            color: tiny_skia::Color::BLACK,
+            thickness: 2.0,
+        }
+    }
+
+    fn frame_count(apng: &[u8]) -> u32 {
+        let reader = png::Decoder::new(std::io::Cursor::new(apng))
+            .read_info()
+            .unwrap();
+        reader.info().animation_control.unwrap().num_frames
+    }
+
+    #[test]
+    fn schedules_strokes_with_capped_pauses() {
+        let first = stroke(&[(1.0, 1.0), (5.0, 5.0), (9.0, 9.0)], &[1000, 1040, 1080]);
+        // Drawn four seconds after the first one ended
+        let second = stroke(&[(1.0, 12.0), (9.0, 12.0)], &[5080, 5120]);
+        let strokes = [first, second];
+        assert_eq!(schedule(&strokes), vec![0, 80 + MAX_PAUSE_MS]);
+
+        // The whole image, the first stroke half drawn, the first stroke done and the
+        // second stroke done; the pause in between only lengthens the frame before
+        let base = Pixmap::new(16, 16).unwrap();
+        let apng = encode_apng(&base, &strokes, Transform::identity()).unwrap();
+        assert_eq!(frame_count(&apng), 4);
+    }
+
+    #[test]
+    fn bounds_non_monotonic_and_long_strokes() {
+        let strokes = [
+            stroke(&[(1.0, 1.0), (9.0, 9.0)], &[1000, 1080]),
+            // A redone stroke drawn before the one it now follows
+            stroke(&[(1.0, 12.0), (9.0, 12.0)], &[500, 540]),
+            // Timestamps going backwards within the stroke
+            stroke(&[(12.0, 1.0), (12.0, 9.0)], &[u32::MAX, 0]),
+            // Held down for a minute
+            stroke(&[(1.0, 14.0), (9.0, 14.0)], &[100, 60_100]),
+            stroke(&[(14.0, 1.0), (14.0, 9.0)], &[]),
+        ];
+        let starts = schedule(&strokes);
+        assert_eq!(
+            starts,
+            vec![
+                0,
+                80 + MAX_PAUSE_MS,
+                120 + 2 * MAX_PAUSE_MS,
+                220 + 2 * MAX_PAUSE_MS,
+                220 + MAX_STROKE_MS + 3 * MAX_PAUSE_MS,
+            ]
+        );
+
+        let base = Pixmap::new(16, 16).unwrap();
+        let apng = encode_apng(&base, &strokes, Transform::identity()).unwrap();
+        assert!(frame_count(&apng) as usize <= 2 + (starts[4] / FRAME_MS) as usize);
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers};
+use xkbcommon::xkb;
+
+use crate::config::MAX_PALETTE_LEN;
+use crate::types::Tool;
+
+/// Everything a key can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Save,
+    /// Saves the strokes and undo history as a session that `--load` can reopen.
+    SaveSession,
+    /// Captures the output with the drawing on top, saves it and exits.
+    Screenshot,
+    /// Like `Screenshot`, but puts the image on the clipboard.
+    Copy,
+    /// Puts just the drawing on the clipboard, without the screen below it.
+    CopyDrawing,
+    /// Saves the drawing as an SVG document next to where PNGs are saved.
+    ExportSvg,
+    /// Saves the drawing as a vector PDF.
+    ExportPdf,
+    /// Saves the drawing as an Excalidraw document.
+    ExportExcalidraw,
+    /// Saves an animation of the drawing being drawn.
+    ExportAnimation,
+    /// Like `ExportAnimation`, but drawn over a screenshot of the output; exits afterwards.
+    ScreenshotAnimation,
+    Undo,
+    Redo,
+    Clear,
+    Tool(Tool),
+    /// Selects a palette entry by its zero-based index.
+    Color(usize),
+    Thicker,
+    Thinner,
+}
+
+/// A keysym together with the modifiers that must be held for it, written as e.g. `Ctrl+Shift+z`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeyCombo {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+    pub keysym: Keysym,
+}
+
+/// The table mapping key combos to actions. User bindings from the config are layered on
+/// top of the defaults; binding a combo to `"none"` removes it.
+#[derive(Clone, Debug)]
+pub struct KeyBindings(HashMap<KeyCombo, Action>);
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quit" => Ok(Action::Quit),
+            "save" => Ok(Action::Save),
+            "save-session" => Ok(Action::SaveSession),
+            "screenshot" => Ok(Action::Screenshot),
+            "copy" => Ok(Action::Copy),
+            "copy-drawing" => Ok(Action::CopyDrawing),
+            "export-svg" => Ok(Action::ExportSvg),
+            "export-pdf" => Ok(Action::ExportPdf),
+            "export-excalidraw" => Ok(Action::ExportExcalidraw),
+            "export-animation" => Ok(Action::ExportAnimation),
+            "screenshot-animation" => Ok(Action::ScreenshotAnimation),
+            "undo" => Ok(Action::Undo),
+            "redo" => Ok(Action::Redo),
+            "clear" => Ok(Action::Clear),
+            "thicker" => Ok(Action::Thicker),
+            "thinner" => Ok(Action::Thinner),
+            _ => {
+                if let Some(tool) = s.strip_prefix("tool-") {
+                    return tool.parse().map(Action::Tool);
+                }
+                if let Some(n) = s.strip_prefix("color-") {
+                    return match n.parse::<usize>() {
+                        Ok(n @ 1..=MAX_PALETTE_LEN) => Ok(Action::Color(n - 1)),
+                        _ => Err(format!(
+                            "invalid palette entry {:?}, expected color-1 to color-{}",
+                            s, MAX_PALETTE_LEN
+                        )),
+                    };
+                }
+                Err(format!("unknown action {:?}", s))
+            }
+        }
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut combo = KeyCombo {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            logo: false,
+            keysym: Keysym::NoSymbol,
+        };
+        let (modifiers, key) = s.rsplit_once('+').unwrap_or(("", s));
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => combo.ctrl = true,
+                "alt" => combo.alt = true,
+                "shift" => combo.shift = true,
+                "super" | "logo" | "mod4" => combo.logo = true,
+                _ => return Err(format!("unknown modifier {:?} in {:?}", modifier, s)),
+            }
+        }
+
+        if !key.contains('\0') {
+            combo.keysym = xkb::keysym_from_name(key, xkb::KEYSYM_CASE_INSENSITIVE);
+        }
+        if combo.keysym == Keysym::NoSymbol {
+            return Err(format!("unknown key {:?} in {:?}", key, s));
+        }
+        combo.keysym = normalize(combo.keysym);
+        Ok(combo)
+    }
+}
+
+/// Letters are matched case-insensitively, with Shift expressed as a modifier instead.
+fn normalize(keysym: Keysym) -> Keysym {
+    match keysym.key_char() {
+        Some(c) if c.is_uppercase() => Keysym::from_char(c.to_lowercase().next().unwrap_or(c)),
+        _ => keysym,
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut bindings = vec![
+            ("Escape", Action::Quit),
+            ("Ctrl+s", Action::Save),
+            ("Ctrl+Alt+s", Action::SaveSession),
+            ("Print", Action::Screenshot),
+            ("Ctrl+c", Action::Copy),
+            ("Ctrl+Shift+c", Action::CopyDrawing),
+            ("Ctrl+Shift+s", Action::ExportSvg),
+            ("Ctrl+Shift+p", Action::ExportPdf),
+            ("Ctrl+Shift+e", Action::ExportExcalidraw),
+            ("Ctrl+Shift+a", Action::ExportAnimation),
+            ("Shift+Print", Action::ScreenshotAnimation),
+            ("Ctrl+z", Action::Undo),
+            ("Ctrl+Shift+z", Action::Redo),
+            ("Ctrl+y", Action::Redo),
+            ("Delete", Action::Clear),
+            ("p", Action::Tool(Tool::Pen)),
+            ("l", Action::Tool(Tool::Line)),
+            ("r", Action::Tool(Tool::Rectangle)),
+            ("e", Action::Tool(Tool::Ellipse)),
+            ("a", Action::Tool(Tool::Arrow)),
+            ("plus", Action::Thicker),
+            ("equal", Action::Thicker),
+            ("minus", Action::Thinner),
+        ];
+        let digits = ["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+        bindings.extend(
+            digits
+                .iter()
+                .enumerate()
+                .map(|(i, digit)| (*digit, Action::Color(i))),
+        );
+
+        KeyBindings(
+            bindings
+                .into_iter()
+                .map(|(combo, action)| (combo.parse().unwrap(), action))
+                .collect(),
+        )
+    }
+}
+
+impl KeyBindings {
+    pub fn action(&self, keysym: Keysym, modifiers: &Modifiers) -> Option<Action> {
+        let combo = KeyCombo {
+            ctrl: modifiers.ctrl,
+            alt: modifiers.alt,
+            shift: modifiers.shift,
+            logo: modifiers.logo,
+            keysym: normalize(keysym),
+        };
+        self.0.get(&combo).copied().or_else(|| {
+            // Shift may have been needed just to produce the keysym (e.g. `plus`)
+            let unshifted = KeyCombo {
+                shift: false,
+                ..combo
+            };
+            modifiers.shift.then(|| self.0.get(&unshifted).copied())?
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyCombo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The value side of a binding: an action, or `"none"` to unbind a default.
+struct BoundAction(Option<Action>);
+
+impl<'de> Deserialize<'de> for BoundAction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s == "none" {
+            return Ok(BoundAction(None));
+        }
+        s.parse()
+            .map(|action| BoundAction(Some(action)))
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let overrides = HashMap::<KeyCombo, BoundAction>::deserialize(deserializer)?;
+        let mut bindings = KeyBindings::default();
+        for (combo, action) in overrides {
+            match action.0 {
+                Some(action) => bindings.0.insert(combo, action),
+                None => bindings.0.remove(&combo),
+            };
+        }
+        Ok(bindings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combo(s: &str) -> KeyCombo {
+        s.parse().unwrap()
+    }
+
+    fn modifiers(ctrl: bool, shift: bool) -> Modifiers {
+        Modifiers {
+            ctrl,
+            shift,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_modifiers_and_key() {
+        let c = combo("Ctrl+Shift+z");
+        assert!(c.ctrl && c.shift && !c.alt && !c.logo);
+        assert_eq!(c.keysym, Keysym::z);
+
+        let c = combo("super+alt+Print");
+        assert!(c.logo && c.alt && !c.ctrl && !c.shift);
+        assert_eq!(c.keysym, Keysym::Print);
+
+        assert_eq!(combo("Control+a"), combo("Ctrl+a"));
+        assert_eq!(combo("Mod4+a"), combo("Super+a"));
+    }
+
+    #[test]
+    fn letters_are_case_insensitive() {
+        assert_eq!(combo("Ctrl+Z"), combo("Ctrl+z"));
+        assert_eq!(combo("A").keysym, Keysym::a);
+    }
+
+    #[test]
+    fn plus_can_be_the_key() {
+        assert_eq!(combo("plus").keysym, Keysym::plus);
+        let c = combo("Ctrl+plus");
+        assert!(c.ctrl);
+        assert_eq!(c.keysym, Keysym::plus);
+    }
+
+    #[test]
+    fn rejects_unknown_modifiers_and_keys() {
+        assert!("Hyper+a".parse::<KeyCombo>().is_err());
+        assert!("Ctrl+nosuchkey".parse::<KeyCombo>().is_err());
+        assert!("Ctrl+".parse::<KeyCombo>().is_err());
+        assert!("".parse::<KeyCombo>().is_err());
+    }
+
+    #[test]
+    fn looks_up_default_bindings() {
+        let bindings = KeyBindings::default();
+        assert_eq!(
+            bindings.action(Keysym::z, &modifiers(true, false)),
+            Some(Action::Undo)
+        );
+        // Shift+z produces an uppercase Z, which still means Ctrl+Shift+z
+        assert_eq!(
+            bindings.action(Keysym::Z, &modifiers(true, true)),
+            Some(Action::Redo)
+        );
+        // Shift is only needed to type `plus`, which is bound without it
+        assert_eq!(
+            bindings.action(Keysym::plus, &modifiers(false, true)),
+            Some(Action::Thicker)
+        );
+        assert_eq!(bindings.action(Keysym::x, &modifiers(false, false)), None);
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert_eq!("tool-arrow".parse(), Ok(Action::Tool(Tool::Arrow)));
+        assert_eq!("color-1".parse(), Ok(Action::Color(0)));
+        assert!("color-0".parse::<Action>().is_err());
+        assert!("tool-brush".parse::<Action>().is_err());
+        assert!("fly".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn config_overrides_and_unbinds_defaults() {
+        let bindings: KeyBindings =
+            toml::from_str("\"q\" = \"quit\"\n\"Delete\" = \"none\"\n").unwrap();
+        assert_eq!(
+            bindings.action(Keysym::q, &modifiers(false, false)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            bindings.action(Keysym::Escape, &modifiers(false, false)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            bindings.action(Keysym::Delete, &modifiers(false, false)),
+            None
+        );
+    }
+}
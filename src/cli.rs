@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use crate::config::{Config, HexColor, LayerConfig, MAX_THICKNESS, MIN_THICKNESS};
+use crate::types::Tool;
+
+const USAGE: &str = "\
+Usage: sway-draw [OPTIONS]
+
+Options:
+  --color <#rrggbb[aa]>    Initial brush color
+  --thickness <px>         Initial brush thickness
+  --tool <tool>            Initial tool: pen, line, rectangle, ellipse or arrow
+  --output <name>          Only draw on the named output (e.g. DP-1) instead of all of them
+  --layer <top|overlay>    Layer-shell layer to place the surface on
+  --background <#rrggbbaa> Fill the surface with a color instead of leaving it transparent
+  --load <file>            Start from a saved session (.json) or PNG drawing
+  --save-on-exit <file>    Save the drawing when exiting, replacing <file>, as a session
+                           if it ends in .json (strftime sequences are expanded)
+  --stdout                 Write the image to stdout when saving (or taking a screenshot)
+                           and exit; exits with status 3 if cancelled instead
+  --record <file>          Record the drawing inputs to a file for replaying later
+  --replay <file>          Play back a recording on the overlay
+  --replay-speed <factor>  Speed up (or slow down) the replay, e.g. 4 [default: 1]
+  -h, --help               Print this help
+  -V, --version            Print the version
+";
+
+/// Launch-time options. Anything given here overrides the config file.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub color: Option<HexColor>,
+    pub thickness: Option<f32>,
+    pub tool: Option<Tool>,
+    pub output: Option<String>,
+    pub layer: Option<LayerConfig>,
+    pub background: Option<HexColor>,
+    pub load: Option<PathBuf>,
+    pub save_on_exit: Option<String>,
+    pub stdout: bool,
+    pub record: Option<String>,
+    pub replay: Option<PathBuf>,
+    pub replay_speed: Option<f64>,
+}
+
+impl Options {
+    /// Parses the process arguments, printing help or version and exiting if asked to.
+    pub fn parse() -> Result<Options, lexopt::Error> {
+        use lexopt::prelude::*;
+
+        let mut options = Options::default();
+        let mut parser = lexopt::Parser::from_env();
+        while let Some(arg) = parser.next()? {
+            match arg {
+                Long("color") => options.color = Some(parser.value()?.parse_with(parse_color)?),
+                Long("thickness") => {
+                    options.thickness = Some(parser.value()?.parse_with(parse_thickness)?)
+                }
+                Long("tool") => options.tool = Some(parser.value()?.parse()?),
+                Long("output") => options.output = Some(parser.value()?.string()?),
+                Long("layer") => options.layer = Some(parser.value()?.parse_with(parse_layer)?),
+                Long("background") => {
+                    options.background = Some(parser.value()?.parse_with(parse_color)?)
+                }
+                Long("load") => options.load = Some(parser.value()?.into()),
+                Long("save-on-exit") => options.save_on_exit = Some(parser.value()?.string()?),
+                Long("stdout") => options.stdout = true,
+                Long("record") => options.record = Some(parser.value()?.string()?),
+                Long("replay") => options.replay = Some(parser.value()?.into()),
+                Long("replay-speed") => {
+                    options.replay_speed = Some(parser.value()?.parse_with(parse_speed)?)
+                }
+                Short('h') | Long("help") => {
+                    print!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                Short('V') | Long("version") => {
+                    println!("sway-draw {}", env!("CARGO_PKG_VERSION"));
+                    std::process::exit(0);
+                }
+                _ => return Err(arg.unexpected()),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Overrides the parts of a config that were also given on the command line.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(color) = self.color {
+            config.tool.color = color;
+        }
+        if let Some(thickness) = self.thickness {
+            config.tool.thickness = thickness;
+        }
+        if let Some(layer) = self.layer {
+            config.ui.layer = layer;
+        }
+    }
+}
+
+fn parse_color(s: &str) -> Result<HexColor, &'static str> {
+    HexColor::parse(s).ok_or("expected #rrggbb or #rrggbbaa")
+}
+
+fn parse_thickness(s: &str) -> Result<f32, String> {
+    let thickness: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(MIN_THICKNESS..=MAX_THICKNESS).contains(&thickness) {
+        return Err(format!(
+            "must be between {} and {}",
+            MIN_THICKNESS, MAX_THICKNESS
+        ));
+    }
+    Ok(thickness)
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err("must be a positive number".to_owned());
+    }
+    Ok(speed)
+}
+
+fn parse_layer(s: &str) -> Result<LayerConfig, &'static str> {
+    match s {
+        "top" => Ok(LayerConfig::Top),
+        "overlay" => Ok(LayerConfig::Overlay),
+        _ => Err("expected top or overlay"),
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use smithay_client_toolkit::data_device_manager::{
+    data_device::DataDeviceHandler,
+    data_offer::{DataOfferHandler, DragOffer},
+    data_source::{CopyPasteSource, DataSourceHandler},
+    WritePipe,
+};
+use wayland_client::{
+    protocol::{
+        wl_data_device::WlDataDevice, wl_data_device_manager::DndAction,
+        wl_data_source::WlDataSource, wl_surface::WlSurface,
+    },
+    Connection, QueueHandle,
+};
+
+use crate::state::AppState;
+
+const PNG_MIME: &str = "image/png";
+
+/// A PNG we currently own the clipboard selection for.
+pub struct Clipboard {
+    source: CopyPasteSource,
+    png: Arc<Vec<u8>>,
+}
+
+impl AppState {
+    /// Offers a PNG on the clipboard. The selection stays ours until another client
+    /// replaces it, which is also when `clipboard` goes back to `None`.
+    pub fn set_clipboard(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        png: Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (Some(manager), Some(device)) = (&self.data_device_manager, &self.data_device) else {
+            return Err("wl_data_device_manager or seat unavailable".into());
+        };
+        let source = manager.create_copy_paste_source(qh, [PNG_MIME]);
+        source.set_selection(device, self.last_serial);
+        self.clipboard = Some(Clipboard {
+            source,
+            png: Arc::new(png),
+        });
+        log::info!("Copied image to the clipboard");
+        Ok(())
+    }
+}
+
+impl DataSourceHandler for AppState {
+    fn accept_mime(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &WlDataSource,
+        _: Option<String>,
+    ) {
+    }
+
+    fn send_request(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        source: &WlDataSource,
+        mime: String,
+        mut fd: WritePipe,
+    ) {
+        let Some(clipboard) = &self.clipboard else {
+            return;
+        };
+        if clipboard.source.inner() != source || mime != PNG_MIME {
+            return;
+        }
+
+        // Write from a thread so a slow or stalled reader can't block the event loop
+        let png = clipboard.png.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = fd.write_all(&png) {
+                log::warn!("Failed to send clipboard contents: {}", e);
+            }
+        });
+    }
+
+    fn cancelled(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, source: &WlDataSource) {
+        if self
+            .clipboard
+            .as_ref()
+            .is_some_and(|clipboard| clipboard.source.inner() == source)
+        {
+            log::debug!("Clipboard selection was replaced");
+            self.clipboard = None;
+        }
+    }
+
+    fn dnd_dropped(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn dnd_finished(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn action(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource, _: DndAction) {}
+}
+
+// We only ever provide data, so incoming offers and drags are ignored.
+impl DataDeviceHandler for AppState {
+    fn enter(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &WlDataDevice,
+        _: f64,
+        _: f64,
+        _: &WlSurface,
+    ) {
+    }
+
+    fn leave(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn motion(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice, _: f64, _: f64) {}
+
+    fn selection(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn drop_performed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+}
+
+impl DataOfferHandler for AppState {
+    fn source_actions(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &mut DragOffer,
+        _: DndAction,
+    ) {
+    }
+
+    fn selected_action(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &mut DragOffer,
+        _: DndAction,
+    ) {
+    }
+}
@@ -0,0 +1,312 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use rustix::fs::inotify;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use smithay_client_toolkit::shell::wlr_layer::{KeyboardInteractivity, Layer};
+
+use crate::bindings::KeyBindings;
+
+pub const MIN_THICKNESS: f32 = 1.0;
+pub const MAX_THICKNESS: f32 = 200.0;
+/// Palette entries are selected with the number keys, so only 1-9 are reachable.
+pub const MAX_PALETTE_LEN: usize = 9;
+
+/// User configuration, read from `$XDG_CONFIG_HOME/sway-draw/config.toml`.
+/// Every field is optional; anything left out falls back to the built-in default.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(deserialize_with = "deserialize_palette")]
+    pub palette: Vec<HexColor>,
+    pub tool: ToolConfig,
+    pub ui: UiConfig,
+    pub save: SaveConfig,
+    pub keybindings: KeyBindings,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolConfig {
+    pub color: HexColor,
+    #[serde(deserialize_with = "deserialize_thickness")]
+    pub thickness: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    pub layer: LayerConfig,
+    pub keyboard_interactivity: InteractivityConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SaveConfig {
+    /// Where to save drawings; `~/` and strftime sequences are expanded.
+    pub path: String,
+    /// Where `screenshot` writes the captured output with the drawing on top.
+    pub screenshot_path: String,
+    pub on_exit: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerConfig {
+    Top,
+    Overlay,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum InteractivityConfig {
+    None,
+    Exclusive,
+    OnDemand,
+}
+
+/// A color written as `#rrggbb` or `#rrggbbaa`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HexColor(pub tiny_skia::Color);
+
+/// Watches the config file for changes with inotify. The parent directory is watched
+/// rather than the file itself, since editors commonly save by replacing the file.
+pub struct ConfigWatcher {
+    fd: OwnedFd,
+    file_name: OsString,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            palette: vec![
+                HexColor(tiny_skia::Color::from_rgba8(255, 0, 0, 255)),
+                HexColor(tiny_skia::Color::from_rgba8(0, 200, 0, 255)),
+                HexColor(tiny_skia::Color::from_rgba8(0, 100, 255, 255)),
+                HexColor(tiny_skia::Color::from_rgba8(255, 215, 0, 255)),
+                HexColor(tiny_skia::Color::from_rgba8(255, 255, 255, 255)),
+                HexColor(tiny_skia::Color::from_rgba8(0, 0, 0, 255)),
+            ],
+            tool: ToolConfig::default(),
+            ui: UiConfig::default(),
+            save: SaveConfig::default(),
+            keybindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl Default for ToolConfig {
+    fn default() -> Self {
+        ToolConfig {
+            color: HexColor(tiny_skia::Color::from_rgba8(255, 0, 0, 255)),
+            thickness: 4.0,
+        }
+    }
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        UiConfig {
+            layer: LayerConfig::Overlay,
+            keyboard_interactivity: InteractivityConfig::OnDemand,
+        }
+    }
+}
+
+impl Default for SaveConfig {
+    fn default() -> Self {
+        SaveConfig {
+            path: "~/Pictures/sway-draw-%Y%m%d-%H%M%S.png".to_owned(),
+            screenshot_path: "~/Pictures/sway-draw-screenshot-%Y%m%d-%H%M%S.png".to_owned(),
+            on_exit: false,
+        }
+    }
+}
+
+impl From<LayerConfig> for Layer {
+    fn from(layer: LayerConfig) -> Self {
+        match layer {
+            LayerConfig::Top => Layer::Top,
+            LayerConfig::Overlay => Layer::Overlay,
+        }
+    }
+}
+
+impl From<InteractivityConfig> for KeyboardInteractivity {
+    fn from(interactivity: InteractivityConfig) -> Self {
+        match interactivity {
+            InteractivityConfig::None => KeyboardInteractivity::None,
+            InteractivityConfig::Exclusive => KeyboardInteractivity::Exclusive,
+            InteractivityConfig::OnDemand => KeyboardInteractivity::OnDemand,
+        }
+    }
+}
+
+impl HexColor {
+    pub fn parse(s: &str) -> Option<HexColor> {
+        let hex = s.strip_prefix('#')?;
+        if !hex.is_ascii() || (hex.len() != 6 && hex.len() != 8) {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+        Some(HexColor(tiny_skia::Color::from_rgba8(
+            channel(0)?,
+            channel(2)?,
+            channel(4)?,
+            alpha,
+        )))
+    }
+
+    /// The color as `#rrggbb`, leaving out the alpha channel.
+    pub fn rgb(&self) -> String {
+        let c = self.0.to_color_u8();
+        format!("#{:02x}{:02x}{:02x}", c.red(), c.green(), c.blue())
+    }
+}
+
+impl<'de> Deserialize<'de> for HexColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        HexColor::parse(&s).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid color {:?}, expected #rrggbb or #rrggbbaa",
+                s
+            ))
+        })
+    }
+}
+
+impl Serialize for HexColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = self.rgb();
+        let alpha = self.0.to_color_u8().alpha();
+        if alpha != 255 {
+            s += &format!("{:02x}", alpha);
+        }
+        serializer.serialize_str(&s)
+    }
+}
+
+fn deserialize_palette<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<HexColor>, D::Error> {
+    let palette = Vec::<HexColor>::deserialize(deserializer)?;
+    if palette.is_empty() || palette.len() > MAX_PALETTE_LEN {
+        return Err(serde::de::Error::custom(format!(
+            "palette must have between 1 and {} colors, got {}",
+            MAX_PALETTE_LEN,
+            palette.len()
+        )));
+    }
+    Ok(palette)
+}
+
+fn deserialize_thickness<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let thickness = f32::deserialize(deserializer)?;
+    if !(MIN_THICKNESS..=MAX_THICKNESS).contains(&thickness) {
+        return Err(serde::de::Error::custom(format!(
+            "thickness must be between {} and {}, got {}",
+            MIN_THICKNESS, MAX_THICKNESS, thickness
+        )));
+    }
+    Ok(thickness)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // toml's error message already carries the line and column of the offending value
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Location of the config file, following the XDG base directory spec.
+    pub fn path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("sway-draw").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_owned(), e))
+    }
+
+    /// Loads the config from its default location. A missing file silently yields the
+    /// defaults; a broken one is reported and also yields the defaults.
+    pub fn load_or_default() -> Config {
+        let Some(path) = Config::path() else {
+            return Config::default();
+        };
+        if !path.exists() {
+            return Config::default();
+        }
+        match Config::load(&path) {
+            Ok(config) => {
+                log::info!("Loaded config from {}", path.display());
+                config
+            }
+            Err(e) => {
+                log::error!("Ignoring config: {}", e);
+                Config::default()
+            }
+        }
+    }
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> std::io::Result<ConfigWatcher> {
+        let (Some(dir), Some(file_name)) = (path.parent(), path.file_name()) else {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        };
+        let fd = inotify::init(inotify::CreateFlags::NONBLOCK | inotify::CreateFlags::CLOEXEC)?;
+        inotify::add_watch(
+            &fd,
+            dir,
+            inotify::WatchFlags::CLOSE_WRITE
+                | inotify::WatchFlags::MOVED_TO
+                | inotify::WatchFlags::DELETE,
+        )?;
+        Ok(ConfigWatcher {
+            fd,
+            file_name: file_name.to_owned(),
+        })
+    }
+
+    /// Drains all pending events, returning whether any of them touched the config file.
+    pub fn changed(&self) -> bool {
+        let mut buf = [std::mem::MaybeUninit::uninit(); 4096];
+        let mut reader = inotify::Reader::new(&self.fd, &mut buf);
+        let mut changed = false;
+        // Reading stops with EAGAIN once the queue is empty
+        while let Ok(event) = reader.next() {
+            let name = event.file_name().map(|name| name.to_bytes());
+            changed |= name == Some(self.file_name.as_encoded_bytes());
+        }
+        changed
+    }
+}
+
+impl AsFd for ConfigWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
@@ -1,16 +1,53 @@
-use crate::types::Stroke;
+use crate::types::{Stroke, Tool};
 
-pub fn render_stroke(pixmap: &mut tiny_skia::PixmapMut, stroke: &Stroke) {
+fn build_path(stroke: &Stroke) -> Option<tiny_skia::Path> {
+    let first = stroke.points.first()?;
+    let last = stroke.points.last()?;
+    let mut pb = tiny_skia::PathBuilder::new();
+    match stroke.tool {
+        Tool::Pen => {
+            pb.move_to(first.x, first.y);
+            for p in &stroke.points[1..] {
+                pb.line_to(p.x, p.y);
+            }
+        }
+        Tool::Line => {
+            pb.move_to(first.x, first.y);
+            pb.line_to(last.x, last.y);
+        }
+        Tool::Rectangle => {
+            let (l, t, r, b) = stroke.shape_ltrb()?;
+            let rect = tiny_skia::Rect::from_ltrb(l, t, r, b)?;
+            pb.push_rect(rect);
+        }
+        Tool::Ellipse => {
+            let (l, t, r, b) = stroke.shape_ltrb()?;
+            let rect = tiny_skia::Rect::from_ltrb(l, t, r, b)?;
+            pb.push_oval(rect);
+        }
+        Tool::Arrow => {
+            let (left, right) = stroke.arrow_head()?;
+            pb.move_to(first.x, first.y);
+            pb.line_to(last.x, last.y);
+            pb.move_to(left.x, left.y);
+            pb.line_to(last.x, last.y);
+            pb.line_to(right.x, right.y);
+        }
+    }
+    pb.finish()
+}
+
+/// Renders a stroke, mapping its global coordinates through `transform`, e.g. onto an
+/// output's surface or at a different resolution.
+pub fn render_stroke_transformed(
+    pixmap: &mut tiny_skia::PixmapMut,
+    stroke: &Stroke,
+    transform: tiny_skia::Transform,
+) {
     if stroke.points.len() < 2 {
         return;
     }
-    let mut pb = tiny_skia::PathBuilder::new();
-    let first = &stroke.points[0];
-    pb.move_to(first.x, first.y);
-    for p in &stroke.points[1..] {
-        pb.line_to(p.x, p.y);
-    }
-    if let Some(path) = pb.finish() {
+    if let Some(path) = build_path(stroke) {
         let mut paint = tiny_skia::Paint::default();
         paint.set_color(stroke.color);
         let stroke_opts = tiny_skia::Stroke {
@@ -19,12 +56,63 @@ pub fn render_stroke(pixmap: &mut tiny_skia::PixmapMut, stroke: &Stroke) {
             line_join: tiny_skia::LineJoin::Round,
             ..Default::default()
         };
-        pixmap.stroke_path(
-            &path,
-            &paint,
-            &stroke_opts,
-            tiny_skia::Transform::identity(),
-            None,
-        );
+        pixmap.stroke_path(&path, &paint, &stroke_opts, transform, None);
+    }
+}
+
+/// Swaps the red and blue channels of RGBA pixels, turning tiny-skia's output into the
+/// `Argb8888` layout for compositors that lack `Abgr8888`. Works on whole 32-bit pixels,
+/// which the compiler vectorizes, rather than swapping bytes one pixel at a time.
+pub fn rgba_to_bgra(data: &mut [u8]) {
+    if data.as_ptr().align_offset(std::mem::align_of::<u32>()) != 0 {
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        return;
     }
+    let (_, pixels, _) = bytemuck::pod_align_to_mut::<u8, u32>(data);
+    for pixel in pixels {
+        let p = u32::from_le(*pixel);
+        *pixel = u32::to_le((p & 0xff00ff00) | ((p >> 16) & 0xff) | ((p & 0xff) << 16));
+    }
+}
+
+/// Renders the pointer cursor for the brush: a disc of the brush's diameter and color,
+/// ringed with a thin dark outline so it stays visible over content of the same color.
+/// The disc is centered in the pixmap, and `scale` is the buffer scale it is shown at.
+pub fn render_brush_preview(
+    pixmap: &mut tiny_skia::PixmapMut,
+    color: tiny_skia::Color,
+    thickness: f32,
+    scale: f32,
+) {
+    let cx = pixmap.width() as f32 / 2.0;
+    let cy = pixmap.height() as f32 / 2.0;
+    let radius = (thickness / 2.0).max(scale);
+    let Some(circle) = tiny_skia::PathBuilder::from_circle(cx, cy, radius) else {
+        return;
+    };
+
+    let mut paint = tiny_skia::Paint::default();
+    paint.set_color(color);
+    pixmap.fill_path(
+        &circle,
+        &paint,
+        tiny_skia::FillRule::Winding,
+        tiny_skia::Transform::identity(),
+        None,
+    );
+
+    paint.set_color_rgba8(0, 0, 0, 200);
+    let outline = tiny_skia::Stroke {
+        width: scale,
+        ..Default::default()
+    };
+    pixmap.stroke_path(
+        &circle,
+        &paint,
+        &outline,
+        tiny_skia::Transform::identity(),
+        None,
+    );
 }
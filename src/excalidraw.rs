@@ -0,0 +1,201 @@
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::config::HexColor;
+use crate::export::Scene;
+use crate::types::{Point, Stroke, Tool};
+
+/// Excalidraw draws freehand lines `strokeWidth * 4.25` wide, so freehand widths are
+/// scaled down by this to come out at the thickness they were drawn with.
+const FREEDRAW_WIDTH_SCALE: f32 = 4.25;
+const IMAGE_ID: &str = "sway-draw-image";
+
+/// Serializes the scene as an Excalidraw document. Each stroke becomes the matching
+/// Excalidraw element, drawn without Excalidraw's hand-drawn roughness so it looks the
+/// way it did on screen; a drawing loaded from a PNG is embedded as an image element.
+pub fn to_excalidraw(scene: &Scene) -> Result<String, Box<dyn std::error::Error>> {
+    let mut elements = Vec::new();
+    let mut files = serde_json::Map::new();
+
+    if let Some(image) = scene.image {
+        let png = base64::engine::general_purpose::STANDARD.encode(image.encode_png()?);
+        files.insert(
+            IMAGE_ID.to_owned(),
+            json!({
+                "id": IMAGE_ID,
+                "mimeType": "image/png",
+                "dataURL": format!("data:image/png;base64,{}", png),
+                "created": 0,
+            }),
+        );
+        let mut element = base_element(elements.len(), "image", 0.0, 0.0);
+        extend(
+            &mut element,
+            json!({
+                "width": image.width(),
+                "height": image.height(),
+                "strokeColor": "transparent",
+                "fileId": IMAGE_ID,
+                "status": "saved",
+                "scale": [1, 1],
+            }),
+        );
+        elements.push(element);
+    }
+
+    for stroke in scene.strokes {
+        if stroke.points.len() < 2 {
+            continue;
+        }
+        if let Some(element) = element(elements.len(), stroke) {
+            elements.push(element);
+        }
+    }
+
+    // Move the scene's corner to the origin, like the other exports do
+    for element in &mut elements {
+        for (key, offset) in [("x", scene.x), ("y", scene.y)] {
+            if let Some(v) = element.get_mut(key).and_then(|v| v.as_f64()) {
+                element[key] = json!(v - offset as f64);
+            }
+        }
+    }
+
+    // Excalidraw has no transparent canvas, so a transparent background becomes white
+    let background = if scene.background.alpha() > 0.0 {
+        HexColor(scene.background).rgb()
+    } else {
+        "#ffffff".to_owned()
+    };
+    let document = json!({
+        "type": "excalidraw",
+        "version": 2,
+        "source": "sway-draw",
+        "elements": elements,
+        "appState": {
+            "viewBackgroundColor": background,
+            "gridSize": null,
+        },
+        "files": files,
+    });
+    Ok(serde_json::to_string(&document)?)
+}
+
+fn element(index: usize, stroke: &Stroke) -> Option<Value> {
+    let first = stroke.points.first()?;
+    let last = stroke.points.last()?;
+    let (l, t, r, b) = stroke.shape_ltrb()?;
+
+    let mut element = match stroke.tool {
+        Tool::Pen => {
+            let mut element = polyline(index, "freedraw", &stroke.points);
+            extend(
+                &mut element,
+                json!({ "pressures": [], "simulatePressure": false }),
+            );
+            element
+        }
+        Tool::Line | Tool::Arrow => {
+            let arrow = stroke.tool == Tool::Arrow;
+            let kind = if arrow { "arrow" } else { "line" };
+            let mut element = polyline(index, kind, &[first.clone(), last.clone()]);
+            extend(
+                &mut element,
+                json!({
+                    "startBinding": null,
+                    "endBinding": null,
+                    "startArrowhead": null,
+                    "endArrowhead": arrow.then_some("arrow"),
+                }),
+            );
+            element
+        }
+        Tool::Rectangle | Tool::Ellipse => {
+            let kind = if stroke.tool == Tool::Rectangle {
+                "rectangle"
+            } else {
+                "ellipse"
+            };
+            let mut element = base_element(index, kind, l, t);
+            extend(&mut element, json!({ "width": r - l, "height": b - t }));
+            element
+        }
+    };
+
+    let width = match stroke.tool {
+        Tool::Pen => stroke.thickness / FREEDRAW_WIDTH_SCALE,
+        _ => stroke.thickness,
+    };
+    extend(
+        &mut element,
+        json!({
+            "strokeColor": HexColor(stroke.color).rgb(),
+            "opacity": (stroke.color.alpha() * 100.0).round(),
+            "strokeWidth": width,
+        }),
+    );
+    Some(element)
+}
+
+/// A linear element; Excalidraw stores its points relative to the element's position.
+fn polyline(index: usize, kind: &str, points: &[Point]) -> Value {
+    let origin = &points[0];
+    let relative: Vec<[f32; 2]> = points
+        .iter()
+        .map(|p| [p.x - origin.x, p.y - origin.y])
+        .collect();
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+    for [x, y] in &relative {
+        min_x = min_x.min(*x);
+        min_y = min_y.min(*y);
+        max_x = max_x.max(*x);
+        max_y = max_y.max(*y);
+    }
+
+    let mut element = base_element(index, kind, origin.x, origin.y);
+    extend(
+        &mut element,
+        json!({
+            "width": max_x - min_x,
+            "height": max_y - min_y,
+            "points": relative,
+            "lastCommittedPoint": null,
+        }),
+    );
+    element
+}
+
+/// The properties every Excalidraw element needs, with plain (not hand-drawn) styling.
+fn base_element(index: usize, kind: &str, x: f32, y: f32) -> Value {
+    json!({
+        "id": format!("sway-draw-{}", index),
+        "type": kind,
+        "x": x,
+        "y": y,
+        "angle": 0,
+        "strokeColor": "#000000",
+        "backgroundColor": "transparent",
+        "fillStyle": "solid",
+        "strokeWidth": 1,
+        "strokeStyle": "solid",
+        "roughness": 0,
+        "opacity": 100,
+        "groupIds": [],
+        "frameId": null,
+        "roundness": null,
+        "seed": index + 1,
+        "version": 1,
+        "versionNonce": index + 1,
+        "isDeleted": false,
+        "boundElements": null,
+        "updated": 0,
+        "link": null,
+        "locked": false,
+    })
+}
+
+fn extend(element: &mut Value, properties: Value) {
+    if let (Value::Object(element), Value::Object(properties)) = (element, properties) {
+        element.extend(properties);
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::types::Stroke;
+
+/// The highest number `create_unique` appends before giving up on finding a free name.
+const MAX_SUFFIX: u32 = 9999;
+
+/// Everything that makes up a drawing, borrowed for exporting it.
+pub struct Scene<'a> {
+    /// Global coordinates of the top left corner, which exports move to their origin.
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub background: tiny_skia::Color,
+    pub image: Option<&'a tiny_skia::Pixmap>,
+    pub strokes: &'a [Stroke],
+}
+
+/// Turns an output path template into a concrete path: a leading `~/` is replaced with
+/// the home directory and strftime sequences (e.g. `%Y%m%d-%H%M%S`) with the local time.
+/// Templates without a `%` are taken literally.
+pub fn expand_path(template: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let (base, rest) = match template.strip_prefix("~/") {
+        Some(rest) => {
+            let home = std::env::var_os("HOME").ok_or("HOME is not set")?;
+            (PathBuf::from(home), rest)
+        }
+        None => (PathBuf::new(), template),
+    };
+    if !rest.contains('%') {
+        return Ok(base.join(rest));
+    }
+    let formatted = jiff::fmt::strtime::format(rest, &jiff::Zoned::now())?;
+    Ok(base.join(formatted))
+}
+
+/// Creates a new file at `path`, or at `name-1.ext`, `name-2.ext`, ... if it is already
+/// taken, so existing files are never overwritten. Missing parent directories are created.
+fn create_unique(path: &Path) -> std::io::Result<(File, PathBuf)> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    for n in 0..=MAX_SUFFIX {
+        let candidate = match (n, &extension) {
+            (0, _) => path.to_owned(),
+            (_, Some(ext)) => path.with_file_name(format!("{}-{}.{}", stem, n, ext)),
+            (_, None) => path.with_file_name(format!("{}-{}", stem, n)),
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!(
+            "{} and its numbered variants up to {} all exist",
+            path.display(),
+            MAX_SUFFIX
+        ),
+    ))
+}
+
+/// Creates a fresh file derived from the path template, for output that is written
+/// incrementally rather than all at once.
+pub fn create_new(template: &str) -> Result<(File, PathBuf), Box<dyn std::error::Error>> {
+    Ok(create_unique(&expand_path(template)?)?)
+}
+
+/// Writes `data` to a fresh file derived from the path template, returning where it went.
+pub fn write_new(template: &str, data: &[u8]) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let (mut file, path) = create_new(template)?;
+    file.write_all(data)?;
+    Ok(path)
+}
+
+/// Writes `data` to the path the template expands to, replacing any existing file.
+pub fn overwrite(template: &str, data: &[u8]) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = expand_path(template)?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, data)?;
+    Ok(path)
+}
+
+/// Saves a pixmap as a PNG with alpha to a fresh file derived from the path template.
+pub fn save_png(
+    pixmap: &tiny_skia::Pixmap,
+    template: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    write_new(template, &pixmap.encode_png()?)
+}
+
+/// Swaps the extension of a path template, so other export formats can reuse the PNG
+/// save path (e.g. `drawing-%H%M%S.png` becomes `drawing-%H%M%S.svg`).
+pub fn with_extension(template: &str, extension: &str) -> String {
+    Path::new(template)
+        .with_extension(extension)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own under the system temp directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sway-draw-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn expands_strftime_sequences() {
+        let year = jiff::Zoned::now().year();
+        let path = expand_path("drawings/%Y/drawing-%%.png").unwrap();
+        assert_eq!(
+            path,
+            PathBuf::from(format!("drawings/{}/drawing-%.png", year))
+        );
+    }
+
+    #[test]
+    fn takes_templates_without_percent_literally() {
+        assert_eq!(
+            expand_path("/tmp/a{b}$c.png").unwrap(),
+            PathBuf::from("/tmp/a{b}$c.png")
+        );
+    }
+
+    #[test]
+    fn expands_home_directory() {
+        let Some(home) = std::env::var_os("HOME").map(PathBuf::from) else {
+            assert!(expand_path("~/drawing.png").is_err());
+            return;
+        };
+        assert_eq!(
+            expand_path("~/drawing.png").unwrap(),
+            home.join("drawing.png")
+        );
+        // The home directory itself is never run through strftime
+        let year = jiff::Zoned::now().year();
+        assert_eq!(
+            expand_path("~/%Y.png").unwrap(),
+            home.join(format!("{}.png", year))
+        );
+        // Only a leading ~/ is expanded
+        assert_eq!(
+            expand_path("~other/drawing.png").unwrap(),
+            PathBuf::from("~other/drawing.png")
+        );
+    }
+
+    #[test]
+    fn numbers_taken_names() {
+        let dir = temp_dir("numbering");
+        let template = dir.join("sub").join("drawing.png");
+        let template = template.to_str().unwrap();
+        let paths: Vec<PathBuf> = (0..3).map(|i| write_new(template, &[i]).unwrap()).collect();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["drawing.png", "drawing-1.png", "drawing-2.png"]);
+        // Earlier files are left alone
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(std::fs::read(path).unwrap(), [i as u8]);
+        }
+
+        let bare = dir.join("notes");
+        let (_, first) = create_unique(&bare).unwrap();
+        let (_, second) = create_unique(&bare).unwrap();
+        assert_eq!((first, second), (bare.clone(), dir.join("notes-1")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
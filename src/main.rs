@@ -1,22 +1,45 @@
+use std::io::Write;
+use std::process::ExitCode;
+
+use smithay_client_toolkit::reexports::{
+    calloop::{generic::Generic, timer::Timer, EventLoop, Interest, Mode, PostAction},
+    calloop_wayland_source::WaylandSource,
+};
 use smithay_client_toolkit::{
     compositor::CompositorState,
-    delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer,
-    delegate_registry, delegate_seat, delegate_shm,
+    data_device_manager::DataDeviceManagerState,
+    delegate_compositor, delegate_data_device, delegate_keyboard, delegate_layer, delegate_output,
+    delegate_pointer, delegate_registry, delegate_seat, delegate_shm,
     output::OutputState,
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
-    seat::SeatState,
-    shell::wlr_layer::{Anchor, KeyboardInteractivity, Layer, LayerShell},
+    seat::{pointer::cursor_shape::CursorShapeManager, SeatState},
+    shell::wlr_layer::LayerShell,
     shm::{slot::SlotPool, Shm},
 };
 use wayland_client::{globals::registry_queue_init, Connection};
 
+mod animation;
+mod bindings;
+mod cli;
+mod clipboard;
+mod config;
 mod draw;
+mod excalidraw;
+mod export;
+mod pdf;
+mod recording;
+mod screenshot;
+mod session;
 mod state;
+mod surface;
+mod svg;
 mod types;
-use smithay_client_toolkit::shell::WaylandSurface;
+use config::{Config, ConfigWatcher};
+use recording::{Recorder, Replay};
+use session::Session;
 use state::AppState;
-use types::Rect;
+use types::Tool;
 
 delegate_compositor!(AppState);
 delegate_output!(AppState);
@@ -26,6 +49,7 @@ delegate_keyboard!(AppState);
 delegate_pointer!(AppState);
 delegate_layer!(AppState);
 delegate_registry!(AppState);
+delegate_data_device!(AppState);
 
 impl ProvidesRegistryState for AppState {
     fn registry(&mut self) -> &mut RegistryState {
@@ -34,10 +58,34 @@ impl ProvidesRegistryState for AppState {
     registry_handlers![OutputState, SeatState];
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Exit status for `--stdout` when the user quit without saving, so pipelines can tell
+/// it apart from success (0), errors (1) and bad arguments (2).
+const EXIT_CANCELLED: u8 = 3;
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let options = match cli::Options::parse() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("sway-draw: {}", e);
+            std::process::exit(2);
+        }
+    };
     log::info!("Starting sway-draw");
 
+    let mut config = Config::load_or_default();
+    options.apply(&mut config);
+
+    let session = match &options.load {
+        Some(path) => Session::load(path).map_err(|e| format!("Failed to load {}", e))?,
+        None => Session {
+            background: tiny_skia::Color::TRANSPARENT,
+            image: None,
+            strokes: Vec::new(),
+            redo_stack: Vec::new(),
+        },
+    };
+
     let conn = Connection::connect_to_env()?;
     let (globals, mut event_queue) = registry_queue_init(&conn).unwrap();
     let qh = event_queue.handle();
@@ -46,53 +94,196 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let layer_shell = LayerShell::bind(&globals, &qh).expect("layer shell is not available");
     let shm = Shm::bind(&globals, &qh).expect("wl_shm is not available");
 
-    let surface = compositor.create_surface(&qh);
-    let layer =
-        layer_shell.create_layer_surface(&qh, surface, Layer::Overlay, Some("sway-draw"), None);
-
-    layer.set_anchor(Anchor::TOP | Anchor::BOTTOM | Anchor::LEFT | Anchor::RIGHT);
-    layer.set_keyboard_interactivity(KeyboardInteractivity::OnDemand);
-    layer.set_exclusive_zone(-1); // Don't move other windows
-
-    // Commit to get the configure event
-    layer.commit();
+    // Starts small: the pool grows to fit the outputs' buffers once their sizes are known
+    let pool = SlotPool::new(256 * 256 * 4, &shm).expect("Failed to create pool");
 
-    let pool = SlotPool::new(1920 * 1080 * 4, &shm).expect("Failed to create pool");
+    // The brush preview cursor lives on its own surface with a small pool of its own
+    let cursor_surface = compositor.create_surface(&qh);
+    let cursor_pool = SlotPool::new(64 * 64 * 4, &shm).expect("Failed to create cursor pool");
+    let cursor_shape_manager = CursorShapeManager::bind(&globals, &qh).ok();
 
     let mut app_state = AppState {
         registry_state: RegistryState::new(&globals),
         seat_state: SeatState::new(&globals, &qh),
         output_state: OutputState::new(&globals, &qh),
         shm,
+        compositor,
+        layer_shell,
+        fractional_scale_manager: globals.bind(&qh, 1..=1, ()).ok(),
+        viewporter: globals.bind(&qh, 1..=1, ()).ok(),
         exit: false,
-        first_configure: true,
         pool,
-        width: 1920,
-        height: 1080,
-        layer,
+        surfaces: Vec::new(),
+        output_filter: options.output.clone(),
+        pointer_output: None,
         keyboard: None,
         keyboard_focus: false,
+        modifiers: Default::default(),
         pointer: None,
+        pointer_enter_serial: None,
+        pressed_buttons: Vec::new(),
+        cursor_surface,
+        cursor_pool,
+        cursor_shape_manager,
+        cursor_shape_device: None,
 
+        tool: options.tool.unwrap_or(Tool::Pen),
+        color: config.tool.color.0,
+        thickness: config.tool.thickness,
+        background: options.background.map_or(session.background, |c| c.0),
+        loaded_image: session.image,
+        config,
         active_stroke: None,
-        completed_canvas: tiny_skia::Pixmap::new(1920, 1080).unwrap(),
-        last_active_stroke_rect: None,
-        pending_damage: Some(Rect {
-            x: 0,
-            y: 0,
-            w: 1920,
-            h: 1080,
-        }),
-        needs_redraw: true,
-        frame_pending: false,
+        strokes: session.strokes,
+        redo_stack: session.redo_stack,
+
+        screencopy_manager: globals.bind(&qh, 1..=3, ()).ok(),
+        capture: None,
+
+        data_device_manager: DataDeviceManagerState::bind(&globals, &qh).ok(),
+        data_device: None,
+        clipboard: None,
+        last_serial: 0,
+
+        stdout: options.stdout,
+        stdout_png: None,
+
+        recorder: None,
+        replaying: false,
     };
 
+    if let Some(template) = &options.record {
+        let recorder = Recorder::create(
+            template,
+            app_state.tool,
+            app_state.color,
+            app_state.thickness,
+        )
+        .map_err(|e| format!("Failed to start recording: {}", e))?;
+        log::info!("Recording to {}", recorder.path().display());
+        app_state.recorder = Some(recorder);
+    }
+    let replay = match &options.replay {
+        Some(path) => {
+            log::info!("Replaying {}", path.display());
+            Some(Replay::load(path, options.replay_speed.unwrap_or(1.0))?)
+        }
+        None => None,
+    };
+
+    // Outputs are announced with the initial output events, and each gets its overlay as
+    // it comes in
+    event_queue.roundtrip(&mut app_state)?;
+    if app_state.surfaces.is_empty() {
+        return Err(match &options.output {
+            Some(name) => format!("No output named {:?}", name),
+            None => "No outputs to draw on".to_owned(),
+        }
+        .into());
+    }
+
+    let mut event_loop: EventLoop<AppState> = EventLoop::try_new()?;
+    WaylandSource::new(conn.clone(), event_queue)
+        .insert(event_loop.handle())
+        .map_err(|e| e.error)?;
+
+    if let Some(path) = Config::path() {
+        match ConfigWatcher::new(&path) {
+            Ok(watcher) => {
+                let options = options.clone();
+                let qh = qh.clone();
+                event_loop.handle().insert_source(
+                    Generic::new(watcher, Interest::READ, Mode::Level),
+                    move |_, watcher, app_state| {
+                        if watcher.changed() {
+                            match Config::load(&path) {
+                                Ok(mut config) => {
+                                    log::info!("Reloading config from {}", path.display());
+                                    options.apply(&mut config);
+                                    app_state.apply_config(&qh, config);
+                                }
+                                Err(e) => log::error!("Keeping previous config: {}", e),
+                            }
+                        }
+                        Ok(PostAction::Continue)
+                    },
+                )?;
+            }
+            Err(e) => log::debug!("Not watching {} for changes: {}", path.display(), e),
+        }
+    }
+
+    if let Some(mut replay) = replay {
+        // Nothing can be drawn before the surfaces are configured
+        while app_state.surfaces.iter().any(|s| !s.configured) && !app_state.exit {
+            event_loop.dispatch(None, &mut app_state)?;
+        }
+        app_state.replaying = true;
+        event_loop
+            .handle()
+            .insert_source(Timer::immediate(), move |_, _, app_state| {
+                replay.step(app_state, &qh)
+            })?;
+    }
+
     loop {
-        event_queue.blocking_dispatch(&mut app_state).unwrap();
+        event_loop.dispatch(None, &mut app_state)?;
         if app_state.exit {
             log::info!("Exiting");
             break;
         }
     }
-    Ok(())
+
+    let mut status = ExitCode::SUCCESS;
+    if options.stdout {
+        match &app_state.stdout_png {
+            Some(png) => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(png)?;
+                stdout.flush()?;
+                log::info!("Wrote image to stdout");
+            }
+            None => {
+                log::info!("Cancelled, nothing written to stdout");
+                status = ExitCode::from(EXIT_CANCELLED);
+            }
+        }
+        // The reading end of the pipe waits for end of file, which must not wait for us to
+        // stop serving the clipboard below
+        let null = std::fs::OpenOptions::new().write(true).open("/dev/null")?;
+        rustix::stdio::dup2_stdout(&null)?;
+    }
+
+    let save = &app_state.config.save;
+    // A file named on the command line is overwritten, while the config's save path
+    // never clobbers earlier saves
+    let save_target = match &options.save_on_exit {
+        Some(template) => Some((template.as_str(), true)),
+        None => save.on_exit.then_some((save.path.as_str(), false)),
+    };
+    if let Some((template, overwrite)) = save_target {
+        // A .json template asks for a session rather than a flattened image
+        let (data, what) = if template.ends_with(".json") {
+            (app_state.session().to_json()?.into_bytes(), "session")
+        } else {
+            (app_state.render_drawing()?.encode_png()?, "drawing")
+        };
+        let path = if overwrite {
+            export::overwrite(template, &data)?
+        } else {
+            export::write_new(template, &data)?
+        };
+        log::info!("Saved {} to {}", what, path.display());
+    }
+
+    if app_state.clipboard.is_some() {
+        // Exiting would drop the selection, so hide the overlay and keep serving it
+        // until another client takes over the clipboard.
+        app_state.unmap();
+        log::info!("Serving clipboard until it is replaced");
+        while app_state.clipboard.is_some() {
+            event_loop.dispatch(None, &mut app_state)?;
+        }
+    }
+    Ok(status)
 }
@@ -0,0 +1,314 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::export::Scene;
+use crate::types::{Stroke, Tool};
+
+/// Control point distance for approximating a quarter ellipse with a cubic Bézier curve.
+const KAPPA: f32 = 0.552_284_8;
+
+/// Serializes scenes as a vector PDF with one page per scene. Pages are sized in points
+/// to match the drawing in pixels, and strokes are written as PDF paths with the same
+/// round caps and joins `draw::render_stroke_transformed` uses. A loaded PNG drawing is
+/// embedded as an image underneath the strokes.
+pub fn to_pdf(pages: &[Scene]) -> Vec<u8> {
+    let mut objects = Objects::default();
+    let catalog = objects.reserve();
+    let page_tree = objects.reserve();
+
+    let mut kids = Vec::new();
+    for scene in pages {
+        let page = objects.reserve();
+        kids.push(page);
+
+        let image = scene.image.map(|image| image_object(&mut objects, image));
+        let (content, alphas) = content(scene);
+        let content_id = objects.add(stream("", &content));
+
+        let mut resources = String::new();
+        if let Some(image) = image {
+            let _ = write!(resources, "/XObject << /Im0 {} 0 R >> ", image);
+        }
+        if !alphas.is_empty() {
+            resources.push_str("/ExtGState << ");
+            for alpha in &alphas {
+                let opacity = num(*alpha as f32 / 255.0);
+                let _ = write!(
+                    resources,
+                    "/GS{} << /CA {} /ca {} >> ",
+                    alpha, opacity, opacity
+                );
+            }
+            resources.push_str(">> ");
+        }
+        objects.set(
+            page,
+            format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << {}>> /Contents {} 0 R >>",
+                page_tree, scene.width, scene.height, resources, content_id
+            )
+            .into_bytes(),
+        );
+    }
+
+    let kids: Vec<String> = kids.iter().map(|id| format!("{} 0 R", id)).collect();
+    objects.set(
+        page_tree,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        )
+        .into_bytes(),
+    );
+    objects.set(
+        catalog,
+        format!("<< /Type /Catalog /Pages {} 0 R >>", page_tree).into_bytes(),
+    );
+    objects.finish(catalog)
+}
+
+/// The content stream of a page, and the stroke opacities (as alpha bytes) it refers to
+/// as `/GS<alpha>` graphics states.
+fn content(scene: &Scene) -> (String, BTreeSet<u8>) {
+    let mut out = String::new();
+    let mut alphas = BTreeSet::new();
+    let height = scene.height as f32;
+
+    // Flip to the surface's y-down coordinates, with the scene's corner at the origin
+    let _ = writeln!(
+        out,
+        "1 0 0 -1 {} {} cm",
+        -scene.x,
+        num(height + scene.y as f32)
+    );
+    if scene.background.alpha() > 0.0 {
+        out.push_str("q ");
+        set_alpha(&mut out, &mut alphas, scene.background);
+        let _ = writeln!(
+            out,
+            "{} rg {} {} {} {} re f Q",
+            rgb(scene.background),
+            scene.x,
+            scene.y,
+            scene.width,
+            scene.height
+        );
+    }
+    if let Some(image) = scene.image {
+        // Image space is y-up, so its top row goes to y = 0
+        let _ = writeln!(
+            out,
+            "q {} 0 0 -{} 0 {} cm /Im0 Do Q",
+            image.width(),
+            image.height(),
+            image.height()
+        );
+    }
+
+    out.push_str("1 J 1 j\n");
+    for stroke in scene.strokes {
+        // Mirrors render_stroke_transformed, which skips strokes that never left their first point
+        if stroke.points.len() < 2 {
+            continue;
+        }
+        out.push_str("q ");
+        set_alpha(&mut out, &mut alphas, stroke.color);
+        let _ = writeln!(out, "{} RG {} w", rgb(stroke.color), num(stroke.thickness));
+        path(&mut out, stroke);
+        out.push_str("S Q\n");
+    }
+    (out, alphas)
+}
+
+fn path(out: &mut String, stroke: &Stroke) {
+    let (Some(first), Some(last), Some((l, t, r, b))) = (
+        stroke.points.first(),
+        stroke.points.last(),
+        stroke.shape_ltrb(),
+    ) else {
+        return;
+    };
+    match stroke.tool {
+        Tool::Pen => {
+            let _ = writeln!(out, "{} {} m", num(first.x), num(first.y));
+            for p in &stroke.points[1..] {
+                let _ = writeln!(out, "{} {} l", num(p.x), num(p.y));
+            }
+        }
+        Tool::Line => {
+            let _ = writeln!(
+                out,
+                "{} {} m {} {} l",
+                num(first.x),
+                num(first.y),
+                num(last.x),
+                num(last.y)
+            );
+        }
+        Tool::Rectangle => {
+            let _ = writeln!(
+                out,
+                "{} {} {} {} re",
+                num(l),
+                num(t),
+                num(r - l),
+                num(b - t)
+            );
+        }
+        Tool::Ellipse => {
+            let (cx, cy) = ((l + r) / 2.0, (t + b) / 2.0);
+            let (rx, ry) = ((r - l) / 2.0, (b - t) / 2.0);
+            let (kx, ky) = (rx * KAPPA, ry * KAPPA);
+            let _ = writeln!(out, "{} {} m", num(cx + rx), num(cy));
+            // One Bézier per quadrant, going around through the bottom, left and top
+            let quadrants = [
+                (cx + rx, cy + ky, cx + kx, cy + ry, cx, cy + ry),
+                (cx - kx, cy + ry, cx - rx, cy + ky, cx - rx, cy),
+                (cx - rx, cy - ky, cx - kx, cy - ry, cx, cy - ry),
+                (cx + kx, cy - ry, cx + rx, cy - ky, cx + rx, cy),
+            ];
+            for (x1, y1, x2, y2, x3, y3) in quadrants {
+                let _ = writeln!(
+                    out,
+                    "{} {} {} {} {} {} c",
+                    num(x1),
+                    num(y1),
+                    num(x2),
+                    num(y2),
+                    num(x3),
+                    num(y3)
+                );
+            }
+            out.push_str("h\n");
+        }
+        Tool::Arrow => {
+            let _ = writeln!(
+                out,
+                "{} {} m {} {} l",
+                num(first.x),
+                num(first.y),
+                num(last.x),
+                num(last.y)
+            );
+            if let Some((left, right)) = stroke.arrow_head() {
+                let _ = writeln!(
+                    out,
+                    "{} {} m {} {} l {} {} l",
+                    num(left.x),
+                    num(left.y),
+                    num(last.x),
+                    num(last.y),
+                    num(right.x),
+                    num(right.y)
+                );
+            }
+        }
+    }
+}
+
+/// Selects the graphics state for a translucent color; opaque ones need none.
+fn set_alpha(out: &mut String, alphas: &mut BTreeSet<u8>, color: tiny_skia::Color) {
+    let alpha = color.to_color_u8().alpha();
+    if alpha < 255 {
+        alphas.insert(alpha);
+        let _ = write!(out, "/GS{} gs ", alpha);
+    }
+}
+
+/// Embeds a pixmap as an RGB image with its alpha channel as a soft mask.
+fn image_object(objects: &mut Objects, image: &tiny_skia::Pixmap) -> usize {
+    let mut rgb = Vec::with_capacity(image.pixels().len() * 3);
+    let mut alpha = Vec::with_capacity(image.pixels().len());
+    for pixel in image.pixels() {
+        let c = pixel.demultiply();
+        rgb.extend_from_slice(&[c.red(), c.green(), c.blue()]);
+        alpha.push(c.alpha());
+    }
+    let size = format!(
+        "/Type /XObject /Subtype /Image /Width {} /Height {} /BitsPerComponent 8",
+        image.width(),
+        image.height()
+    );
+    let mask = objects.add(stream(&format!("{} /ColorSpace /DeviceGray", size), &alpha));
+    objects.add(stream(
+        &format!("{} /ColorSpace /DeviceRGB /SMask {} 0 R", size, mask),
+        &rgb,
+    ))
+}
+
+/// A Flate-compressed stream object with the given extra dictionary entries.
+fn stream(dictionary: &str, data: impl AsRef<[u8]>) -> Vec<u8> {
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(data.as_ref(), 6);
+    let mut out = format!(
+        "<< {} /Length {} /Filter /FlateDecode >>\nstream\n",
+        dictionary,
+        compressed.len()
+    )
+    .into_bytes();
+    out.extend_from_slice(&compressed);
+    out.extend_from_slice(b"\nendstream");
+    out
+}
+
+/// The numbered objects of a PDF file, in order.
+#[derive(Default)]
+struct Objects(Vec<Vec<u8>>);
+
+impl Objects {
+    /// Allocates an object number for an object that is filled in later with `set`.
+    fn reserve(&mut self) -> usize {
+        self.0.push(Vec::new());
+        self.0.len()
+    }
+
+    fn set(&mut self, id: usize, body: Vec<u8>) {
+        self.0[id - 1] = body;
+    }
+
+    fn add(&mut self, body: Vec<u8>) -> usize {
+        self.0.push(body);
+        self.0.len()
+    }
+
+    /// Writes out the file with its cross-reference table.
+    fn finish(self, root: usize) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.0.len());
+        for (i, body) in self.0.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.0.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(table, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.0.len() + 1,
+            root,
+            xref
+        );
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
+
+fn rgb(color: tiny_skia::Color) -> String {
+    format!(
+        "{} {} {}",
+        num(color.red()),
+        num(color.green()),
+        num(color.blue())
+    )
+}
+
+/// Formats a number with at most three decimals, which is plenty at screen resolution.
+fn num(v: f32) -> String {
+    format!("{}", (v * 1000.0).round() / 1000.0)
+}
@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use smithay_client_toolkit::reexports::calloop::timer::TimeoutAction;
+use wayland_client::QueueHandle;
+
+use crate::bindings::Action;
+use crate::config::{HexColor, MAX_THICKNESS, MIN_THICKNESS};
+use crate::export;
+use crate::state::AppState;
+use crate::types::{Point, Tool};
+
+const FORMAT: &str = "sway-draw-recording";
+const VERSION: u32 = 1;
+
+/// One input-level step of a drawing session. Tool settings are recorded as the values
+/// they ended up with rather than the key that changed them, so a replay doesn't depend
+/// on the palette or keybindings it runs with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "input", rename_all = "kebab-case")]
+pub enum Input {
+    Press { x: f32, y: f32 },
+    Motion { x: f32, y: f32 },
+    Release,
+    Tool { tool: Tool },
+    Color { color: HexColor },
+    Thickness { thickness: f32 },
+    Undo,
+    Redo,
+    Clear,
+}
+
+/// An input and when it happened, in milliseconds since the recording started.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: u32,
+    #[serde(flatten)]
+    pub input: Input,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+}
+
+/// Appends inputs to a recording file as JSON lines, one entry each.
+pub struct Recorder {
+    out: BufWriter<File>,
+    path: PathBuf,
+    /// Compositor timestamp of the first input, which becomes time zero.
+    start: Option<u32>,
+    last: u32,
+}
+
+/// The remaining inputs of a recording being played back.
+pub struct Replay {
+    entries: VecDeque<Entry>,
+    speed: f64,
+    /// When playback began, which is only once the overlay is up.
+    start: Option<Instant>,
+}
+
+impl Recorder {
+    /// Starts a recording at a fresh file derived from the path template. The current
+    /// tool settings are recorded up front so the replay starts from the same ones.
+    pub fn create(
+        template: &str,
+        tool: Tool,
+        color: tiny_skia::Color,
+        thickness: f32,
+    ) -> Result<Recorder, Box<dyn std::error::Error>> {
+        let (file, path) = export::create_new(template)?;
+        let mut recorder = Recorder {
+            out: BufWriter::new(file),
+            path,
+            start: None,
+            last: 0,
+        };
+        let header = Header {
+            format: FORMAT.to_owned(),
+            version: VERSION,
+        };
+        writeln!(recorder.out, "{}", serde_json::to_string(&header)?)?;
+        recorder.write(Input::Tool { tool });
+        recorder.write(Input::Color {
+            color: HexColor(color),
+        });
+        recorder.write(Input::Thickness { thickness });
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records an input with the compositor timestamp of the event that caused it.
+    pub fn record(&mut self, time: u32, input: Input) {
+        let start = *self.start.get_or_insert(time);
+        // Timestamps wrap around after ~49 days; keep them from running backwards
+        self.last = self.last.max(time.wrapping_sub(start));
+        self.write(input);
+    }
+
+    /// Records an input that has no timestamp of its own, at the time of the last one.
+    pub fn record_now(&mut self, input: Input) {
+        self.write(input);
+    }
+
+    fn write(&mut self, input: Input) {
+        let flush = matches!(input, Input::Release);
+        let entry = Entry {
+            time: self.last,
+            input,
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(std::io::Error::from)
+            .and_then(|line| writeln!(self.out, "{}", line));
+        // Flushing once per stroke keeps most of the recording if we crash
+        let result = result.and_then(|()| if flush { self.out.flush() } else { Ok(()) });
+        if let Err(e) = result {
+            log::error!("Failed to write to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl Replay {
+    /// Reads a recording to be played back `speed` times as fast as it was recorded.
+    pub fn load(path: &Path, speed: f64) -> Result<Replay, Box<dyn std::error::Error>> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut lines = BufReader::new(file).lines();
+        let at = |n: usize, e: &dyn std::fmt::Display| format!("{}:{}: {}", path.display(), n, e);
+
+        let header = lines.next().ok_or_else(|| at(1, &"empty recording"))??;
+        let header: Header = serde_json::from_str(&header).map_err(|e| at(1, &e))?;
+        if header.format != FORMAT {
+            return Err(at(1, &format!("not a {}", FORMAT)).into());
+        }
+        if header.version > VERSION {
+            return Err(at(
+                1,
+                &format!(
+                    "recording version {} is newer than the supported version {}",
+                    header.version, VERSION
+                ),
+            )
+            .into());
+        }
+
+        let mut entries = VecDeque::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push_back(serde_json::from_str(&line).map_err(|e| at(i + 2, &e))?);
+        }
+        Ok(Replay {
+            entries,
+            speed,
+            start: None,
+        })
+    }
+
+    /// How long after the start of the replay an entry is due.
+    fn due(&self, entry: &Entry) -> Duration {
+        Duration::from_secs_f64(entry.time as f64 / 1000.0 / self.speed)
+    }
+
+    /// Timer callback: performs every input that is due and schedules the next one.
+    pub fn step(&mut self, state: &mut AppState, qh: &QueueHandle<AppState>) -> TimeoutAction {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let elapsed = start.elapsed();
+        while let Some(entry) = self.entries.front() {
+            if self.due(entry) > elapsed {
+                return TimeoutAction::ToInstant(start + self.due(entry));
+            }
+            if let Some(entry) = self.entries.pop_front() {
+                state.replay_input(qh, entry.time, entry.input);
+            }
+        }
+        log::info!("Replay finished");
+        state.replaying = false;
+        TimeoutAction::Drop
+    }
+}
+
+impl AppState {
+    /// Records an input if a recording is running.
+    pub fn record(&mut self, time: Option<u32>, input: Input) {
+        if let Some(recorder) = &mut self.recorder {
+            match time {
+                Some(time) => recorder.record(time, input),
+                None => recorder.record_now(input),
+            }
+        }
+    }
+
+    /// Records the effect of a key action, once it has been performed.
+    pub fn record_action(&mut self, time: u32, action: Action) {
+        let input = match action {
+            Action::Tool(tool) => Input::Tool { tool },
+            Action::Color(_) => Input::Color {
+                color: HexColor(self.color),
+            },
+            Action::Thicker | Action::Thinner => Input::Thickness {
+                thickness: self.thickness,
+            },
+            Action::Undo => Input::Undo,
+            Action::Redo => Input::Redo,
+            Action::Clear => Input::Clear,
+            _ => return,
+        };
+        self.record(Some(time), input);
+    }
+
+    fn replay_input(&mut self, qh: &QueueHandle<Self>, time: u32, input: Input) {
+        match input {
+            Input::Press { x, y } => self.begin_stroke(Point { x, y }, time),
+            Input::Motion { x, y } => self.extend_stroke(Point { x, y }, time),
+            Input::Release => self.finish_stroke(),
+            Input::Tool { tool } => self.perform(qh, Action::Tool(tool)),
+            Input::Color { color } => {
+                self.color = color.0;
+                self.update_cursor();
+            }
+            Input::Thickness { thickness } => {
+                self.thickness = thickness.clamp(MIN_THICKNESS, MAX_THICKNESS);
+                self.update_cursor();
+            }
+            Input::Undo => self.perform(qh, Action::Undo),
+            Input::Redo => self.perform(qh, Action::Redo),
+            Input::Clear => self.perform(qh, Action::Clear),
+        }
+        self.redraw(qh);
+    }
+}
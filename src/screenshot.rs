@@ -0,0 +1,387 @@
+use smithay_client_toolkit::shm::slot::Buffer;
+use wayland_client::{
+    protocol::{wl_output, wl_shm},
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use crate::animation;
+use crate::draw::render_stroke_transformed;
+use crate::export;
+use crate::state::AppState;
+use crate::surface::output_transform;
+use crate::types::Rect;
+
+/// Where a finished screenshot goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureTarget {
+    File,
+    Clipboard,
+    /// An animation of the drawing being drawn on top of the screenshot.
+    Animation,
+}
+
+/// An in-progress screenshot of one output.
+pub enum Capture {
+    /// The output's overlay is being cleared and we are waiting for that frame to be
+    /// shown, so the drawing doesn't end up in the captured image twice. `blanked` is set
+    /// once the blank buffer has been committed; frame callbacks for earlier frames must
+    /// not start the copy.
+    Hiding {
+        target: CaptureTarget,
+        output: wl_output::WlOutput,
+        blanked: bool,
+    },
+    Copying(Frame),
+}
+
+pub struct Frame {
+    target: CaptureTarget,
+    output: wl_output::WlOutput,
+    frame: ZwlrScreencopyFrameV1,
+    /// Format, width, height and stride of the shm buffer the compositor asked for.
+    shm_info: Option<(wl_shm::Format, u32, u32, u32)>,
+    buffer: Option<Buffer>,
+    y_invert: bool,
+}
+
+impl Capture {
+    /// The output being captured, whose overlay stays blank meanwhile.
+    pub fn output(&self) -> &wl_output::WlOutput {
+        match self {
+            Capture::Hiding { output, .. } => output,
+            Capture::Copying(frame) => &frame.output,
+        }
+    }
+}
+
+impl AppState {
+    /// Screenshots the output the pointer is on, or the first one if it hasn't been on
+    /// any yet.
+    pub fn start_screenshot(&mut self, qh: &QueueHandle<Self>, target: CaptureTarget) {
+        if self.capture.is_some() {
+            return;
+        }
+        let output = self
+            .pointer_output
+            .clone()
+            .filter(|output| self.surfaces.iter().any(|s| s.output == *output))
+            .or_else(|| self.surfaces.first().map(|s| s.output.clone()));
+        let (Some(_), Some(output)) = (&self.screencopy_manager, output) else {
+            log::error!(
+                "Cannot take a screenshot: zwlr_screencopy_manager_v1 or output unavailable"
+            );
+            return;
+        };
+
+        // Blank the overlay; the capture starts from the frame callback once that is shown
+        self.refresh_output(&output);
+        self.capture = Some(Capture::Hiding {
+            target,
+            output,
+            blanked: false,
+        });
+        self.redraw(qh);
+    }
+
+    /// Called from the frame callback: asks the compositor for a copy of the output once
+    /// the blanked overlay is on screen.
+    pub fn begin_copy(&mut self, qh: &QueueHandle<Self>) {
+        let Some(manager) = &self.screencopy_manager else {
+            return;
+        };
+        let Some(Capture::Hiding {
+            target,
+            output,
+            blanked: true,
+        }) = &self.capture
+        else {
+            return;
+        };
+        let (target, output) = (*target, output.clone());
+        let frame = manager.capture_output(0, &output, qh, ());
+        self.capture = Some(Capture::Copying(Frame {
+            target,
+            output,
+            frame,
+            shm_info: None,
+            buffer: None,
+            y_invert: false,
+        }));
+    }
+
+    fn copy_frame(&mut self, qh: &QueueHandle<Self>) {
+        let Some(Capture::Copying(frame)) = &mut self.capture else {
+            return;
+        };
+        let Some((format, width, height, stride)) = frame.shm_info else {
+            log::error!("Compositor offered no supported shm format for the screenshot");
+            frame.frame.destroy();
+            self.end_capture(qh);
+            return;
+        };
+        match self
+            .pool
+            .create_buffer(width as i32, height as i32, stride as i32, format)
+        {
+            Ok((buffer, _)) => {
+                frame.frame.copy(buffer.wl_buffer());
+                frame.buffer = Some(buffer);
+            }
+            Err(e) => {
+                log::error!("Failed to create screenshot buffer: {}", e);
+                frame.frame.destroy();
+                self.end_capture(qh);
+            }
+        }
+    }
+
+    fn finish_screenshot(&mut self, qh: &QueueHandle<Self>) {
+        let Some(Capture::Copying(frame)) = self.capture.take() else {
+            return;
+        };
+        frame.frame.destroy();
+        // Bring the drawing back should saving fail
+        self.refresh_output(&frame.output);
+        let (Some((format, width, height, stride)), Some(buffer)) = (frame.shm_info, frame.buffer)
+        else {
+            self.end_capture(qh);
+            return;
+        };
+        let Some(data) = buffer.canvas(&mut self.pool) else {
+            log::error!("Screenshot buffer is still in use by the compositor");
+            self.end_capture(qh);
+            return;
+        };
+        let Some(pixmap) = to_pixmap(data, format, width, height, stride, frame.y_invert) else {
+            log::error!("Failed to convert screenshot");
+            self.end_capture(qh);
+            return;
+        };
+
+        let Some((bounds, buffer_transform)) = self
+            .surfaces
+            .iter()
+            .find(|s| s.output == frame.output)
+            .map(|s| (s.bounds(), s.buffer_transform))
+        else {
+            log::error!("Output went away while taking a screenshot");
+            self.end_capture(qh);
+            return;
+        };
+        let Some(mut pixmap) = upright(pixmap, buffer_transform) else {
+            log::error!("Failed to rotate screenshot");
+            self.end_capture(qh);
+            return;
+        };
+        let transform = self.composite_background(&mut pixmap, &bounds);
+        if frame.target != CaptureTarget::Animation {
+            for stroke in &self.strokes {
+                render_stroke_transformed(&mut pixmap.as_mut(), stroke, transform);
+            }
+        }
+        let result = match frame.target {
+            CaptureTarget::File if self.stdout => pixmap
+                .encode_png()
+                .map(|png| self.stdout_png = Some(png))
+                .map_err(Into::into),
+            CaptureTarget::File => export::save_png(&pixmap, &self.config.save.screenshot_path)
+                .map(|path| log::info!("Saved screenshot to {}", path.display())),
+            CaptureTarget::Clipboard => pixmap
+                .encode_png()
+                .map_err(Into::into)
+                .and_then(|png| self.set_clipboard(qh, png)),
+            CaptureTarget::Animation => animation::encode_apng(&pixmap, &self.strokes, transform)
+                .and_then(|apng| {
+                    let template =
+                        export::with_extension(&self.config.save.screenshot_path, "apng");
+                    export::write_new(&template, &apng)
+                })
+                .map(|path| log::info!("Saved animation to {}", path.display())),
+        };
+        match result {
+            // Capturing the screen resolves the annotation session
+            Ok(()) => self.exit = true,
+            Err(e) => {
+                log::error!("Failed to save screenshot: {}", e);
+                self.end_capture(qh);
+            }
+        }
+    }
+
+    /// Renders the background and loaded drawing on top of an image of `area` (in global
+    /// coordinates), e.g. a captured output, which may be at a higher resolution than its
+    /// surface. Returns the transform from global coordinates, so strokes can be
+    /// re-rendered rather than scaled and stay sharp.
+    pub fn composite_background(
+        &self,
+        pixmap: &mut tiny_skia::Pixmap,
+        area: &Rect,
+    ) -> tiny_skia::Transform {
+        let transform = tiny_skia::Transform::from_scale(
+            pixmap.width() as f32 / area.w as f32,
+            pixmap.height() as f32 / area.h as f32,
+        )
+        .pre_translate(-area.x as f32, -area.y as f32);
+
+        if let Some(rect) =
+            tiny_skia::Rect::from_xywh(area.x as f32, area.y as f32, area.w as f32, area.h as f32)
+        {
+            let mut paint = tiny_skia::Paint::default();
+            paint.set_color(self.background);
+            pixmap.fill_rect(rect, &paint, transform, None);
+        }
+        if let Some(image) = &self.loaded_image {
+            let paint = tiny_skia::PixmapPaint {
+                quality: tiny_skia::FilterQuality::Bilinear,
+                ..Default::default()
+            };
+            pixmap.draw_pixmap(0, 0, image.as_ref(), &paint, transform, None);
+        }
+        transform
+    }
+
+    /// Abandons the capture and brings the drawing back on screen.
+    fn end_capture(&mut self, qh: &QueueHandle<Self>) {
+        if let Some(capture) = self.capture.take() {
+            self.refresh_output(capture.output());
+        }
+        self.redraw(qh);
+    }
+
+    /// Marks an output's whole overlay for redrawing.
+    fn refresh_output(&mut self, output: &wl_output::WlOutput) {
+        for surface in &mut self.surfaces {
+            if surface.output == *output {
+                surface.add_damage(&surface.bounds());
+            }
+        }
+    }
+}
+
+/// Converts captured shm data to a tiny-skia pixmap. Only the common 8-bit RGB formats are
+/// supported; the alpha channel is ignored since an output has no transparency.
+fn to_pixmap(
+    data: &[u8],
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+    y_invert: bool,
+) -> Option<tiny_skia::Pixmap> {
+    // Byte offsets of red and blue within a little-endian pixel
+    let (r, b) = match format {
+        wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888 => (2, 0),
+        wl_shm::Format::Abgr8888 | wl_shm::Format::Xbgr8888 => (0, 2),
+        _ => return None,
+    };
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)?;
+    let row_len = width as usize * 4;
+    for (y, dst) in pixmap.data_mut().chunks_exact_mut(row_len).enumerate() {
+        let src_y = if y_invert { height as usize - 1 - y } else { y };
+        let start = src_y * stride as usize;
+        let src = data.get(start..start + row_len)?;
+        for (d, s) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+            d.copy_from_slice(&[s[r], s[1], s[b], 255]);
+        }
+    }
+    Some(pixmap)
+}
+
+/// Turns a capture, which comes in the output's buffer orientation, the way the output
+/// shows it.
+fn upright(
+    pixmap: tiny_skia::Pixmap,
+    transform: wl_output::Transform,
+) -> Option<tiny_skia::Pixmap> {
+    let (width, height) = match transform {
+        wl_output::Transform::Normal => return Some(pixmap),
+        wl_output::Transform::_90
+        | wl_output::Transform::_270
+        | wl_output::Transform::Flipped90
+        | wl_output::Transform::Flipped270 => (pixmap.height(), pixmap.width()),
+        _ => (pixmap.width(), pixmap.height()),
+    };
+    let inverse = output_transform(transform, width as f32, height as f32).invert()?;
+    let mut rotated = tiny_skia::Pixmap::new(width, height)?;
+    rotated.draw_pixmap(
+        0,
+        0,
+        pixmap.as_ref(),
+        &tiny_skia::PixmapPaint::default(),
+        inverse,
+        None,
+    );
+    Some(rotated)
+}
+
+pub fn is_supported_format(format: wl_shm::Format) -> bool {
+    matches!(
+        format,
+        wl_shm::Format::Argb8888
+            | wl_shm::Format::Xrgb8888
+            | wl_shm::Format::Abgr8888
+            | wl_shm::Format::Xbgr8888
+    )
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for AppState {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrScreencopyManagerV1,
+        _: <ZwlrScreencopyManagerV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // zwlr_screencopy_manager_v1 has no events
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        use zwlr_screencopy_frame_v1::Event;
+
+        let Some(Capture::Copying(frame)) = &mut state.capture else {
+            return;
+        };
+        match event {
+            Event::Buffer {
+                format: WEnum::Value(format),
+                width,
+                height,
+                stride,
+            } => {
+                if frame.shm_info.is_none() && is_supported_format(format) {
+                    frame.shm_info = Some((format, width, height, stride));
+                }
+                // Before version 3 there is no buffer_done, so this is the only offer
+                if proxy.version() < 3 {
+                    state.copy_frame(qh);
+                }
+            }
+            Event::BufferDone => state.copy_frame(qh),
+            Event::Flags {
+                flags: WEnum::Value(flags),
+            } => frame.y_invert = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert),
+            Event::Ready { .. } => state.finish_screenshot(qh),
+            Event::Failed => {
+                log::error!("Compositor failed to capture the screen");
+                frame.frame.destroy();
+                state.end_capture(qh);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{HexColor, MAX_THICKNESS, MIN_THICKNESS};
+use crate::types::{Point, Stroke, Tool};
+
+/// Identifies our files, so loading something else fails with a clear error.
+const FORMAT: &str = "sway-draw";
+/// Bumped whenever the document layout changes incompatibly. Older versions must stay
+/// loadable; newer ones are rejected rather than half-understood.
+const VERSION: u32 = 1;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// A drawing that can be written to disk and picked up again later.
+pub struct Session {
+    pub background: tiny_skia::Color,
+    /// A raster drawing the strokes are drawn on top of, e.g. from loading a PNG.
+    pub image: Option<tiny_skia::Pixmap>,
+    pub strokes: Vec<Stroke>,
+    /// Undone strokes, so a reopened session can still redo them.
+    pub redo_stack: Vec<Stroke>,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Invalid(PathBuf, String),
+}
+
+/// The on-disk layout. Kept separate from the in-memory types so those can change
+/// without breaking existing files.
+#[derive(Serialize, Deserialize)]
+struct Document {
+    format: String,
+    version: u32,
+    background: HexColor,
+    /// Base64-encoded PNG.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    strokes: Vec<StrokeData>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    redo: Vec<StrokeData>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StrokeData {
+    tool: String,
+    color: HexColor,
+    thickness: f32,
+    /// `[x, y]` pairs in global (compositor layout) coordinates.
+    points: Vec<[f32; 2]>,
+    /// Millisecond timestamps of the points, if known.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    times: Vec<u32>,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SessionError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            SessionError::Invalid(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl Session {
+    /// Reads a saved session. A PNG is accepted too and becomes a session with the image
+    /// as its background drawing and no strokes.
+    pub fn load(path: &Path) -> Result<Session, SessionError> {
+        let data = std::fs::read(path).map_err(|e| SessionError::Io(path.to_owned(), e))?;
+        let invalid = |e: String| SessionError::Invalid(path.to_owned(), e);
+
+        if data.starts_with(PNG_SIGNATURE) {
+            let image = tiny_skia::Pixmap::decode_png(&data).map_err(|e| invalid(e.to_string()))?;
+            return Ok(Session {
+                background: tiny_skia::Color::TRANSPARENT,
+                image: Some(image),
+                strokes: Vec::new(),
+                redo_stack: Vec::new(),
+            });
+        }
+
+        let document: Document =
+            serde_json::from_slice(&data).map_err(|e| SessionError::Parse(path.to_owned(), e))?;
+        if document.format != FORMAT {
+            return Err(invalid(format!("not a {} session", FORMAT)));
+        }
+        if document.version > VERSION {
+            return Err(invalid(format!(
+                "session version {} is newer than the supported version {}",
+                document.version, VERSION
+            )));
+        }
+
+        let image = match document.image {
+            Some(encoded) => {
+                let png = base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|e| invalid(format!("invalid image: {}", e)))?;
+                Some(
+                    tiny_skia::Pixmap::decode_png(&png)
+                        .map_err(|e| invalid(format!("invalid image: {}", e)))?,
+                )
+            }
+            None => None,
+        };
+        let strokes = |data: Vec<StrokeData>| -> Result<Vec<Stroke>, SessionError> {
+            data.into_iter()
+                .map(|stroke| stroke.try_into().map_err(invalid))
+                .collect()
+        };
+        Ok(Session {
+            background: document.background.0,
+            image,
+            strokes: strokes(document.strokes)?,
+            redo_stack: strokes(document.redo)?,
+        })
+    }
+
+    /// Serializes the session as compact JSON; freehand strokes have far too many points
+    /// for pretty-printing to be useful.
+    pub fn to_json(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let image = match &self.image {
+            Some(image) => {
+                Some(base64::engine::general_purpose::STANDARD.encode(image.encode_png()?))
+            }
+            None => None,
+        };
+        let document = Document {
+            format: FORMAT.to_owned(),
+            version: VERSION,
+            background: HexColor(self.background),
+            image,
+            strokes: self.strokes.iter().map(StrokeData::from).collect(),
+            redo: self.redo_stack.iter().map(StrokeData::from).collect(),
+        };
+        Ok(serde_json::to_string(&document)?)
+    }
+}
+
+impl From<&Stroke> for StrokeData {
+    fn from(stroke: &Stroke) -> Self {
+        StrokeData {
+            tool: stroke.tool.name().to_owned(),
+            color: HexColor(stroke.color),
+            thickness: stroke.thickness,
+            points: stroke.points.iter().map(|p| [p.x, p.y]).collect(),
+            times: stroke.times.clone(),
+        }
+    }
+}
+
+impl TryFrom<StrokeData> for Stroke {
+    type Error = String;
+
+    fn try_from(data: StrokeData) -> Result<Self, Self::Error> {
+        let tool: Tool = data.tool.parse()?;
+        if !(MIN_THICKNESS..=MAX_THICKNESS).contains(&data.thickness) {
+            return Err(format!(
+                "stroke thickness must be between {} and {}, got {}",
+                MIN_THICKNESS, MAX_THICKNESS, data.thickness
+            ));
+        }
+        if !data.times.is_empty() && data.times.len() != data.points.len() {
+            return Err(format!(
+                "stroke has {} points but {} timestamps",
+                data.points.len(),
+                data.times.len()
+            ));
+        }
+        if data.times.windows(2).any(|pair| pair[1] < pair[0]) {
+            return Err("stroke timestamps go backwards".to_owned());
+        }
+        Ok(Stroke {
+            tool,
+            points: data
+                .points
+                .into_iter()
+                .map(|[x, y]| Point { x, y })
+                .collect(),
+            times: data.times,
+            color: data.color.0,
+            thickness: data.thickness,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `data` to a file of its own and loads it as a session.
+    fn load(name: &str, data: &[u8]) -> Result<Session, SessionError> {
+        let path = std::env::temp_dir().join(format!(
+            "sway-draw-test-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, data).unwrap();
+        let session = Session::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        session
+    }
+
+    fn stroke(tool: Tool, points: &[(f32, f32)], times: Vec<u32>) -> Stroke {
+        Stroke {
+            tool,
+            points: points.iter().map(|&(x, y)| Point { x, y }).collect(),
+            times,
+            color: tiny_skia::Color::from_rgba8(255, 0, 128, 200),
+            thickness: 6.5,
+        }
+    }
+
+    fn assert_same(a: &[Stroke], b: &[Stroke]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert_eq!(a.tool, b.tool);
+            assert_eq!(a.thickness, b.thickness);
+            assert_eq!(a.times, b.times);
+            assert_eq!(a.color.to_color_u8(), b.color.to_color_u8());
+            let points = |s: &Stroke| s.points.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>();
+            assert_eq!(points(a), points(b));
+        }
+    }
+
+    #[test]
+    fn round_trips_strokes_and_redo_stack() {
+        let mut image = tiny_skia::Pixmap::new(4, 3).unwrap();
+        image.fill(tiny_skia::Color::from_rgba8(10, 20, 30, 255));
+        let session = Session {
+            background: tiny_skia::Color::from_rgba8(255, 255, 255, 255),
+            image: Some(image.clone()),
+            strokes: vec![
+                stroke(Tool::Pen, &[(1.0, 2.0), (3.5, -4.25)], vec![100, 116]),
+                stroke(Tool::Arrow, &[(0.0, 0.0), (50.0, 60.0)], Vec::new()),
+            ],
+            redo_stack: vec![stroke(Tool::Ellipse, &[(5.0, 5.0), (9.0, 7.0)], Vec::new())],
+        };
+
+        let loaded = load("round-trip", session.to_json().unwrap().as_bytes()).unwrap();
+        assert_eq!(
+            loaded.background.to_color_u8(),
+            session.background.to_color_u8()
+        );
+        assert_eq!(loaded.image.unwrap().data(), image.data());
+        assert_same(&loaded.strokes, &session.strokes);
+        assert_same(&loaded.redo_stack, &session.redo_stack);
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        let foreign = br##"{"format":"other","version":1,"background":"#00000000","strokes":[]}"##;
+        assert!(matches!(
+            load("foreign", foreign),
+            Err(SessionError::Invalid(..))
+        ));
+
+        let newer =
+            br##"{"format":"sway-draw","version":99,"background":"#00000000","strokes":[]}"##;
+        assert!(matches!(
+            load("newer", newer),
+            Err(SessionError::Invalid(..))
+        ));
+
+        assert!(matches!(
+            load("garbage", b"{"),
+            Err(SessionError::Parse(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_strokes() {
+        let mismatched = br##"{"format":"sway-draw","version":1,"background":"#00000000","strokes":[{"tool":"pen","color":"#ff0000","thickness":2,"points":[[0,0],[1,1]],"times":[5]}]}"##;
+        assert!(matches!(
+            load("mismatched", mismatched),
+            Err(SessionError::Invalid(..))
+        ));
+
+        let backwards = br##"{"format":"sway-draw","version":1,"background":"#00000000","strokes":[{"tool":"pen","color":"#ff0000","thickness":2,"points":[[0,0],[1,1]],"times":[9,5]}]}"##;
+        assert!(matches!(
+            load("backwards", backwards),
+            Err(SessionError::Invalid(..))
+        ));
+
+        let thin = br##"{"format":"sway-draw","version":1,"background":"#00000000","strokes":[{"tool":"pen","color":"#ff0000","thickness":0.5,"points":[]}]}"##;
+        assert!(matches!(load("thin", thin), Err(SessionError::Invalid(..))));
+
+        let thick = br##"{"format":"sway-draw","version":1,"background":"#00000000","strokes":[{"tool":"pen","color":"#ff0000","thickness":1e30,"points":[]}]}"##;
+        assert!(matches!(
+            load("thick", thick),
+            Err(SessionError::Invalid(..))
+        ));
+    }
+}
@@ -1,27 +1,48 @@
 use std::num::NonZeroU32;
 
 use smithay_client_toolkit::{
-    compositor::CompositorHandler,
+    compositor::{CompositorHandler, CompositorState},
+    data_device_manager::{data_device::DataDevice, DataDeviceManagerState},
     output::{OutputHandler, OutputState},
     registry::RegistryState,
     seat::{
         keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers, RawModifiers},
-        pointer::{PointerEvent, PointerEventKind, PointerHandler},
+        pointer::{cursor_shape::CursorShapeManager, PointerEvent, PointerEventKind, PointerHandler},
         Capability, SeatHandler, SeatState,
     },
     shell::{
-        wlr_layer::{LayerShellHandler, LayerSurface, LayerSurfaceConfigure},
+        wlr_layer::{LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure},
         WaylandSurface,
     },
     shm::{slot::SlotPool, Shm, ShmHandler},
 };
+use smithay_client_toolkit::reexports::protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::{
+    Shape, WpCursorShapeDeviceV1,
+};
+use smithay_client_toolkit::reexports::protocols::wp::{
+    fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+    viewporter::client::wp_viewporter::WpViewporter,
+};
 use wayland_client::{
-    protocol::{wl_keyboard, wl_output, wl_pointer, wl_seat, wl_surface},
+    protocol::{wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm, wl_surface},
     Connection, QueueHandle,
 };
-
-use crate::draw::render_stroke;
-use crate::types::{Point, Rect, Stroke};
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
+
+use crate::animation;
+use crate::bindings::Action;
+use crate::clipboard::Clipboard;
+use crate::config::{Config, MAX_THICKNESS, MIN_THICKNESS};
+use crate::draw::{render_brush_preview, render_stroke_transformed, rgba_to_bgra};
+use crate::excalidraw;
+use crate::export::{self, Scene};
+use crate::pdf;
+use crate::recording::{Input, Recorder};
+use crate::screenshot::{Capture, CaptureTarget};
+use crate::session::Session;
+use crate::surface::{buffer_format, logical_position, OutputSurface};
+use crate::svg;
+use crate::types::{Point, Rect, Stroke, Tool};
 
 pub struct AppState {
     pub registry_state: RegistryState,
@@ -29,55 +50,124 @@ pub struct AppState {
     pub output_state: OutputState,
     pub shm: Shm,
 
+    pub compositor: CompositorState,
+    pub layer_shell: LayerShell,
+    pub fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    pub viewporter: Option<WpViewporter>,
+
     pub exit: bool,
-    pub first_configure: bool,
     pub pool: SlotPool,
-    pub width: u32,
-    pub height: u32,
-    pub layer: LayerSurface,
+    /// One overlay per output, created and destroyed as outputs come and go.
+    pub surfaces: Vec<OutputSurface>,
+    /// Set with `--output`: only the output with this name gets an overlay.
+    pub output_filter: Option<String>,
+    /// The output the pointer was last on, which is the one screenshots capture.
+    pub pointer_output: Option<wl_output::WlOutput>,
     pub keyboard: Option<wl_keyboard::WlKeyboard>,
     pub keyboard_focus: bool,
+    pub modifiers: Modifiers,
     pub pointer: Option<wl_pointer::WlPointer>,
-
+    pub pointer_enter_serial: Option<u32>,
+    /// Pointer buttons held down, as far as we know: a release off our surfaces never
+    /// reaches us, so they are forgotten once the pointer leaves all of them.
+    pub pressed_buttons: Vec<u32>,
+    pub cursor_surface: wl_surface::WlSurface,
+    pub cursor_pool: SlotPool,
+    pub cursor_shape_manager: Option<CursorShapeManager>,
+    pub cursor_shape_device: Option<WpCursorShapeDeviceV1>,
+
+    pub config: Config,
+    pub tool: Tool,
+    pub color: tiny_skia::Color,
+    pub thickness: f32,
+    pub background: tiny_skia::Color,
+    pub loaded_image: Option<tiny_skia::Pixmap>,
     pub active_stroke: Option<Stroke>,
-
-    pub completed_canvas: tiny_skia::Pixmap,
-    pub last_active_stroke_rect: Option<Rect>,
-    pub pending_damage: Option<Rect>,
-    pub needs_redraw: bool,
-    pub frame_pending: bool,
+    pub strokes: Vec<Stroke>,
+    pub redo_stack: Vec<Stroke>,
+
+    pub screencopy_manager: Option<ZwlrScreencopyManagerV1>,
+    pub capture: Option<Capture>,
+
+    pub data_device_manager: Option<DataDeviceManagerState>,
+    pub data_device: Option<DataDevice>,
+    pub clipboard: Option<Clipboard>,
+    /// Serial of the latest input event, needed to claim the clipboard selection.
+    pub last_serial: u32,
+
+    /// Set with `--stdout`: `save` and `screenshot` produce `stdout_png` instead of files.
+    pub stdout: bool,
+    /// The image to write to stdout on exit. Exiting without one means the user cancelled.
+    pub stdout_png: Option<Vec<u8>>,
+
+    pub recorder: Option<Recorder>,
+    /// Set while a recording plays back, which locks out drawing with the pointer and every
+    /// key action but quitting.
+    pub replaying: bool,
 }
 
 impl CompositorHandler for AppState {
     fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
     ) {
+        let Some(index) = self.surface_index(surface) else {
+            return;
+        };
+        let surface = &mut self.surfaces[index];
+        // A fractional scale, when available, is more precise than the output's
+        if surface.fractional_scale.is_none() && surface.scale != new_factor as f64 {
+            surface.scale = new_factor as f64;
+            surface.reallocate(self.background, self.loaded_image.as_ref(), &self.strokes);
+            // The brush preview follows the scale of the output it is on
+            self.update_cursor();
+            self.redraw(qh);
+        }
     }
 
     fn transform_changed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
     ) {
+        let Some(index) = self.surface_index(surface) else {
+            return;
+        };
+        let surface = &mut self.surfaces[index];
+        if surface.buffer_transform != new_transform {
+            surface.buffer_transform = new_transform;
+            surface.reallocate(self.background, self.loaded_image.as_ref(), &self.strokes);
+            self.redraw(qh);
+        }
     }
 
     fn frame(
         &mut self,
         _conn: &Connection,
         qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
+        surface: &wl_surface::WlSurface,
         _time: u32,
     ) {
-        self.frame_pending = false;
-        if self.needs_redraw {
-            self.draw(qh);
+        let Some(index) = self.surface_index(surface) else {
+            return;
+        };
+        self.surfaces[index].frame_pending = false;
+        if let Some(Capture::Hiding {
+            output,
+            blanked: true,
+            ..
+        }) = &self.capture
+        {
+            if *output == self.surfaces[index].output {
+                self.begin_copy(qh);
+            }
         }
+        self.redraw(qh);
     }
 
     fn surface_enter(
@@ -107,64 +197,101 @@ impl OutputHandler for AppState {
     fn new_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
     ) {
+        let Some(info) = self.output_state.info(&output) else {
+            return;
+        };
+        if let Some(name) = &self.output_filter {
+            if info.name.as_ref() != Some(name) {
+                return;
+            }
+        }
+        log::debug!(
+            "Adding overlay on output {}",
+            info.name.as_deref().unwrap_or("(unnamed)")
+        );
+        let mut surface = OutputSurface::new(
+            qh,
+            &self.compositor,
+            &self.layer_shell,
+            output,
+            &info,
+            &self.config,
+        );
+        if let (Some(manager), Some(viewporter)) =
+            (&self.fractional_scale_manager, &self.viewporter)
+        {
+            surface.enable_fractional_scale(qh, manager, viewporter);
+        }
+        self.surfaces.push(surface);
     }
 
     fn update_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
     ) {
+        let Some(info) = self.output_state.info(&output) else {
+            return;
+        };
+        // Moving an output moves the part of the drawing it shows
+        let position = logical_position(&info);
+        for surface in &mut self.surfaces {
+            if surface.output == output && surface.position != position {
+                surface.position = position;
+                surface.rerender(self.background, self.loaded_image.as_ref(), &self.strokes);
+            }
+        }
+        self.redraw(qh);
     }
 
     fn output_destroyed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
     ) {
+        self.remove_surfaces(|surface| surface.output == output);
     }
 }
 
 impl LayerShellHandler for AppState {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-        self.exit = true;
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        self.remove_surfaces(|surface| surface.layer.wl_surface() == layer.wl_surface());
     }
 
     fn configure(
         &mut self,
         _conn: &Connection,
         qh: &QueueHandle<Self>,
-        _layer: &LayerSurface,
+        layer: &LayerSurface,
         configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        let width = NonZeroU32::new(configure.new_size.0).map_or(256, NonZeroU32::get);
-        let height = NonZeroU32::new(configure.new_size.1).map_or(256, NonZeroU32::get);
-
-        if width != self.width || height != self.height {
-            self.width = width;
-            self.height = height;
-            // Re-create the completed canvas if size changes
-            self.completed_canvas = tiny_skia::Pixmap::new(self.width, self.height).unwrap();
-            self.pending_damage = Some(Rect {
-                x: 0,
-                y: 0,
-                w: self.width,
-                h: self.height,
-            });
-        }
-
-        if self.first_configure {
-            self.first_configure = false;
-            self.needs_redraw = true;
-            if !self.frame_pending {
-                self.draw(qh);
-            }
+        let Some(index) = self.surface_index(layer.wl_surface()) else {
+            return;
+        };
+        let surface = &mut self.surfaces[index];
+        // A zero size leaves it to us, and the output's size is what we'd pick
+        let width = NonZeroU32::new(configure.new_size.0).map_or(surface.width, NonZeroU32::get);
+        let height = NonZeroU32::new(configure.new_size.1).map_or(surface.height, NonZeroU32::get);
+
+        if !surface.configured
+            || width != surface.width
+            || height != surface.height
+            || surface.completed_canvas.is_none()
+        {
+            surface.configured = true;
+            surface.width = width;
+            surface.height = height;
+            // Re-create the completed canvas if size changes. Strokes may already exist
+            // before the first configure, e.g. from --load
+            surface.reallocate(self.background, self.loaded_image.as_ref(), &self.strokes);
         }
+        self.redraw(qh);
     }
 }
 
@@ -185,10 +312,20 @@ impl SeatHandler for AppState {
         if capability == Capability::Keyboard && self.keyboard.is_none() {
             let keyboard = self.seat_state.get_keyboard(qh, &seat, None).unwrap();
             self.keyboard = Some(keyboard);
+            if self.data_device.is_none() {
+                self.data_device = self
+                    .data_device_manager
+                    .as_ref()
+                    .map(|manager| manager.get_data_device(qh, &seat));
+            }
         }
 
         if capability == Capability::Pointer && self.pointer.is_none() {
             let pointer = self.seat_state.get_pointer(qh, &seat).unwrap();
+            self.cursor_shape_device = self
+                .cursor_shape_manager
+                .as_ref()
+                .map(|manager| manager.get_shape_device(&pointer, qh));
             self.pointer = Some(pointer);
         }
     }
@@ -205,6 +342,9 @@ impl SeatHandler for AppState {
         }
 
         if capability == Capability::Pointer && self.pointer.is_some() {
+            if let Some(device) = self.cursor_shape_device.take() {
+                device.destroy();
+            }
             self.pointer.take().unwrap().release();
         }
     }
@@ -219,12 +359,13 @@ impl KeyboardHandler for AppState {
         _: &QueueHandle<Self>,
         _: &wl_keyboard::WlKeyboard,
         surface: &wl_surface::WlSurface,
-        _: u32,
+        serial: u32,
         _: &[u32],
         _keysyms: &[Keysym],
     ) {
-        if self.layer.wl_surface() == surface {
+        if self.surface_index(surface).is_some() {
             self.keyboard_focus = true;
+            self.last_serial = serial;
         }
     }
 
@@ -236,7 +377,7 @@ impl KeyboardHandler for AppState {
         surface: &wl_surface::WlSurface,
         _: u32,
     ) {
-        if self.layer.wl_surface() == surface {
+        if self.surface_index(surface).is_some() {
             self.keyboard_focus = false;
         }
     }
@@ -244,13 +385,23 @@ impl KeyboardHandler for AppState {
     fn press_key(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         _: &wl_keyboard::WlKeyboard,
-        _: u32,
+        serial: u32,
         event: KeyEvent,
     ) {
-        if event.keysym == Keysym::Escape {
-            self.exit = true;
+        self.last_serial = serial;
+        if let Some(action) = self
+            .config
+            .keybindings
+            .action(event.keysym, &self.modifiers)
+        {
+            // A replay must not be changed under its feet, so it can only be quit
+            if self.replaying && action != Action::Quit {
+                return;
+            }
+            self.perform(qh, action);
+            self.record_action(event.time, action);
         }
     }
 
@@ -280,10 +431,11 @@ impl KeyboardHandler for AppState {
         _: &QueueHandle<Self>,
         _: &wl_keyboard::WlKeyboard,
         _serial: u32,
-        _modifiers: Modifiers,
+        modifiers: Modifiers,
         _raw_modifiers: RawModifiers,
         _layout: u32,
     ) {
+        self.modifiers = modifiers;
     }
 }
 
@@ -296,75 +448,96 @@ impl PointerHandler for AppState {
         events: &[PointerEvent],
     ) {
         use PointerEventKind::*;
-        let mut needs_redraw = false;
+
+        // Moving between outputs leaves one of our surfaces and enters another at once
+        let entered = events
+            .iter()
+            .any(|e| matches!(e.kind, Enter { .. }) && self.surface_index(&e.surface).is_some());
 
         for event in events {
-            if &event.surface != self.layer.wl_surface() {
+            let Some(index) = self.surface_index(&event.surface) else {
                 continue;
-            }
+            };
+            // Strokes are kept in global coordinates, so they line up across outputs
+            let (x, y) = self.surfaces[index].position;
+            let point = Point {
+                x: event.position.0 as f32 + x as f32,
+                y: event.position.1 as f32 + y as f32,
+            };
             match event.kind {
-                Enter { .. } => log::debug!("Pointer entered"),
-                Leave { .. } => {
-                    if let Some(stroke) = self.active_stroke.take() {
-                        if let Some(bounds) = stroke.bounding_box() {
-                            // Bake into completed canvas
-                            render_stroke(&mut self.completed_canvas.as_mut(), &stroke);
-                            self.pending_damage = match &self.pending_damage {
-                                Some(d) => Some(d.union(&bounds)),
-                                None => Some(bounds),
-                            };
-                        }
-                        self.needs_redraw = true;
+                Enter { serial } => {
+                    log::debug!("Pointer entered");
+                    self.pointer_output = Some(self.surfaces[index].output.clone());
+                    self.pointer_enter_serial = Some(serial);
+                    self.update_cursor();
+                    // Coming back with the button up ends a stroke whose release we missed
+                    if self.pressed_buttons.is_empty()
+                        && self.active_stroke.is_some()
+                        && !self.replaying
+                    {
+                        self.record(None, Input::Release);
+                        self.finish_stroke();
                     }
                 }
-                Motion { .. } => {
-                    if let Some(stroke) = &mut self.active_stroke {
-                        stroke.points.push(Point {
-                            x: event.position.0 as f32,
-                            y: event.position.1 as f32,
-                        });
-                        needs_redraw = true;
+                // Live drawing is locked out while a recording plays back
+                Motion { .. } | Press { .. } | Release { .. } if self.replaying => {}
+                // A stroke dragged onto another output carries on there: the next motion
+                // comes from that output's surface, in the same global coordinates
+                Leave { .. } => {
+                    if !entered {
+                        self.pressed_buttons.clear();
                     }
                 }
-                Press { button, .. } => {
-                    if button == 272 {
-                        let stroke = Stroke {
-                            points: vec![Point {
-                                x: event.position.0 as f32,
-                                y: event.position.1 as f32,
-                            }],
-                            color: tiny_skia::Color::from_rgba8(255, 0, 0, 255),
-                            thickness: 4.0,
-                        };
-                        self.active_stroke = Some(stroke);
-                        needs_redraw = true;
+                Motion { time } => {
+                    if self.active_stroke.is_some() {
+                        self.record(
+                            Some(time),
+                            Input::Motion {
+                                x: point.x,
+                                y: point.y,
+                            },
+                        );
+                        self.extend_stroke(point, time);
                     }
                 }
-                Release { button, .. } => {
+                Press {
+                    button,
+                    serial,
+                    time,
+                } => {
+                    self.last_serial = serial;
+                    if !self.pressed_buttons.contains(&button) {
+                        self.pressed_buttons.push(button);
+                    }
                     if button == 272 {
-                        if let Some(stroke) = self.active_stroke.take() {
-                            if let Some(bounds) = stroke.bounding_box() {
-                                // Bake into completed canvas
-                                render_stroke(&mut self.completed_canvas.as_mut(), &stroke);
-                                self.pending_damage = match &self.pending_damage {
-                                    Some(d) => Some(d.union(&bounds)),
-                                    None => Some(bounds),
-                                };
-                            }
-                            self.needs_redraw = true;
+                        // The release of a stroke dragged off our surfaces entirely (e.g.
+                        // onto an output left out with --output) never reaches us
+                        if self.active_stroke.is_some() {
+                            self.record(Some(time), Input::Release);
+                            self.finish_stroke();
                         }
+                        self.record(
+                            Some(time),
+                            Input::Press {
+                                x: point.x,
+                                y: point.y,
+                            },
+                        );
+                        self.begin_stroke(point, time);
+                    }
+                }
+                Release { button, time, .. } => {
+                    self.pressed_buttons.retain(|&b| b != button);
+                    if button == 272 && self.active_stroke.is_some() {
+                        self.record(Some(time), Input::Release);
+                        self.finish_stroke();
                     }
                 }
                 Axis { .. } => {}
             }
         }
 
-        if needs_redraw {
-            self.needs_redraw = true;
-            if !self.frame_pending {
-                self.draw(qh);
-            }
-        }
+        self.redraw(qh);
     }
 }
 
@@ -375,107 +548,371 @@ impl ShmHandler for AppState {
 }
 
 impl AppState {
-    pub fn draw(&mut self, qh: &QueueHandle<Self>) {
-        let width = self.width;
-        let height = self.height;
-        let stride = width as i32 * 4;
-
-        let (buffer, canvas) = self
-            .pool
-            .create_buffer(
-                width as i32,
-                height as i32,
-                stride,
-                wayland_client::protocol::wl_shm::Format::Argb8888,
-            )
-            .expect("create buffer");
-
-        // 1. Calculate the final dirty rect for this frame
-        let mut dirty_rect = self.pending_damage.take();
-
-        // Add last frame's active stroke area so we erase it
-        if let Some(r) = &self.last_active_stroke_rect {
-            dirty_rect = match dirty_rect {
-                Some(d) => Some(d.union(r)),
-                None => Some(r.clone()),
-            };
+    pub fn perform(&mut self, qh: &QueueHandle<Self>, action: Action) {
+        match action {
+            Action::Quit => self.exit = true,
+            Action::Screenshot => self.start_screenshot(qh, CaptureTarget::File),
+            Action::Copy => self.start_screenshot(qh, CaptureTarget::Clipboard),
+            Action::CopyDrawing => {
+                if let Err(e) = self
+                    .encode_drawing()
+                    .and_then(|png| self.set_clipboard(qh, png))
+                {
+                    log::error!("Failed to copy drawing: {}", e);
+                }
+            }
+            // When piping, saving hands the drawing to stdout and ends the session
+            Action::Save if self.stdout => match self.encode_drawing() {
+                Ok(png) => {
+                    self.stdout_png = Some(png);
+                    self.exit = true;
+                }
+                Err(e) => log::error!("Failed to encode drawing: {}", e),
+            },
+            Action::Save => {
+                let result = self
+                    .render_drawing()
+                    .and_then(|pixmap| export::save_png(&pixmap, &self.config.save.path));
+                match result {
+                    Ok(path) => log::info!("Saved drawing to {}", path.display()),
+                    Err(e) => log::error!("Failed to save drawing: {}", e),
+                }
+            }
+            Action::SaveSession => {
+                let result = self.session().to_json().and_then(|json| {
+                    let template = export::with_extension(&self.config.save.path, "json");
+                    export::write_new(&template, json.as_bytes())
+                });
+                match result {
+                    Ok(path) => log::info!("Saved session to {}", path.display()),
+                    Err(e) => log::error!("Failed to save session: {}", e),
+                }
+            }
+            Action::ExportSvg => {
+                let svg = svg::to_svg(&self.scene());
+                let template = export::with_extension(&self.config.save.path, "svg");
+                match export::write_new(&template, svg.as_bytes()) {
+                    Ok(path) => log::info!("Exported drawing to {}", path.display()),
+                    Err(e) => log::error!("Failed to export SVG: {}", e),
+                }
+            }
+            Action::ExportPdf => {
+                let pdf = pdf::to_pdf(&[self.scene()]);
+                let template = export::with_extension(&self.config.save.path, "pdf");
+                match export::write_new(&template, &pdf) {
+                    Ok(path) => log::info!("Exported drawing to {}", path.display()),
+                    Err(e) => log::error!("Failed to export PDF: {}", e),
+                }
+            }
+            Action::ExportExcalidraw => {
+                let result = excalidraw::to_excalidraw(&self.scene()).and_then(|json| {
+                    let template = export::with_extension(&self.config.save.path, "excalidraw");
+                    export::write_new(&template, json.as_bytes())
+                });
+                match result {
+                    Ok(path) => log::info!("Exported drawing to {}", path.display()),
+                    Err(e) => log::error!("Failed to export Excalidraw document: {}", e),
+                }
+            }
+            Action::ExportAnimation => {
+                let result = self.export_animation().and_then(|apng| {
+                    let template = export::with_extension(&self.config.save.path, "apng");
+                    export::write_new(&template, &apng)
+                });
+                match result {
+                    Ok(path) => log::info!("Saved animation to {}", path.display()),
+                    Err(e) => log::error!("Failed to export animation: {}", e),
+                }
+            }
+            Action::ScreenshotAnimation => self.start_screenshot(qh, CaptureTarget::Animation),
+            Action::Undo => {
+                if let Some(stroke) = self.strokes.pop() {
+                    self.redo_stack.push(stroke);
+                    self.rerender_canvas();
+                }
+            }
+            Action::Redo => {
+                if let Some(stroke) = self.redo_stack.pop() {
+                    self.bake_stroke(stroke);
+                }
+            }
+            Action::Clear => {
+                self.strokes.clear();
+                self.redo_stack.clear();
+                self.rerender_canvas();
+            }
+            Action::Tool(tool) => {
+                self.tool = tool;
+                self.update_cursor();
+            }
+            Action::Color(index) => {
+                if let Some(color) = self.config.palette.get(index) {
+                    self.color = color.0;
+                    self.update_cursor();
+                }
+            }
+            Action::Thicker => {
+                self.thickness = (self.thickness * 1.25).min(MAX_THICKNESS);
+                self.update_cursor();
+            }
+            Action::Thinner => {
+                self.thickness = (self.thickness / 1.25).max(MIN_THICKNESS);
+                self.update_cursor();
+            }
         }
 
-        // Add current frame's active stroke
-        let current_active_rect = self.active_stroke.as_ref().and_then(|s| s.bounding_box());
-        if let Some(r) = &current_active_rect {
-            dirty_rect = match dirty_rect {
-                Some(d) => Some(d.union(r)),
-                None => Some(r.clone()),
-            };
+        self.redraw(qh);
+    }
+
+    /// Starts a new stroke with the current tool settings.
+    pub fn begin_stroke(&mut self, point: Point, time: u32) {
+        self.active_stroke = Some(Stroke {
+            tool: self.tool,
+            points: vec![point],
+            times: vec![time],
+            color: self.color,
+            thickness: self.thickness,
+        });
+        self.request_redraw();
+    }
+
+    pub fn extend_stroke(&mut self, point: Point, time: u32) {
+        if let Some(stroke) = &mut self.active_stroke {
+            stroke.extend(point, time);
+            self.request_redraw();
         }
+    }
 
-        self.last_active_stroke_rect = current_active_rect;
-
-        // If nothing needs to be redrawn, we just attach buffer and commit (or we could even skip committing entirely,
-        // but smithay might expect a frame callback response. Safe bet is to draw nothing and commit).
-        let dirty = match dirty_rect {
-            Some(r) => {
-                // Constrain the dirty rect to the actual window bounds
-                let screen_bound = Rect {
-                    x: 0,
-                    y: 0,
-                    w: width,
-                    h: height,
-                };
-                r.intersect(&screen_bound)
-            }
-            None => None,
-        };
+    /// Ends the active stroke, if any, and commits it to the drawing.
+    pub fn finish_stroke(&mut self) {
+        if let Some(stroke) = self.active_stroke.take() {
+            self.redo_stack.clear();
+            self.bake_stroke(stroke);
+        }
+    }
+
+    /// Renders a stroke into the completed canvases and records it in the history.
+    fn bake_stroke(&mut self, stroke: Stroke) {
+        for surface in &mut self.surfaces {
+            surface.bake(&stroke);
+        }
+        self.strokes.push(stroke);
+        self.request_redraw();
+    }
+
+    /// Rebuilds the completed canvases from scratch, e.g. after strokes were removed.
+    pub fn rerender_canvas(&mut self) {
+        for surface in &mut self.surfaces {
+            surface.rerender(self.background, self.loaded_image.as_ref(), &self.strokes);
+        }
+    }
+
+    /// The area covered by all outputs, in global coordinates. Outputs may sit left of or
+    /// above the global origin, so it doesn't necessarily start there. Drawings are saved
+    /// and exported at this size.
+    pub fn extent(&self) -> Rect {
+        self.surfaces
+            .iter()
+            .map(|surface| surface.bounds())
+            .reduce(|extent, bounds| extent.union(&bounds))
+            .unwrap_or(Rect {
+                x: 0,
+                y: 0,
+                w: 0,
+                h: 0,
+            })
+    }
 
-        if let Some(dirty) = dirty {
-            // 2. Clear only the dirty part of our wayland buffer and composite the 'done' strokes
-            for y in dirty.y..(dirty.y + dirty.h as i32) {
-                let y = y as usize;
-                let start = (y * width as usize + dirty.x as usize) * 4;
-                let len = (dirty.w as usize) * 4;
-                if start + len <= canvas.len() {
-                    canvas[start..start + len]
-                        .copy_from_slice(&self.completed_canvas.data()[start..start + len]);
+    /// Renders the drawing over its background, across all outputs.
+    pub fn render_drawing(&self) -> Result<tiny_skia::Pixmap, Box<dyn std::error::Error>> {
+        let extent = self.extent();
+        let mut pixmap =
+            tiny_skia::Pixmap::new(extent.w, extent.h).ok_or("no output to draw on")?;
+        let transform = self.composite_background(&mut pixmap, &extent);
+        for stroke in &self.strokes {
+            render_stroke_transformed(&mut pixmap.as_mut(), stroke, transform);
+        }
+        Ok(pixmap)
+    }
+
+    pub fn encode_drawing(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(self.render_drawing()?.encode_png()?)
+    }
+
+    /// Animates the drawing over its background, at the size of all outputs.
+    fn export_animation(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let extent = self.extent();
+        let mut base = tiny_skia::Pixmap::new(extent.w, extent.h).ok_or("no output to draw on")?;
+        let transform = self.composite_background(&mut base, &extent);
+        animation::encode_apng(&base, &self.strokes, transform)
+    }
+
+    pub fn scene(&self) -> Scene<'_> {
+        let extent = self.extent();
+        Scene {
+            x: extent.x,
+            y: extent.y,
+            width: extent.w,
+            height: extent.h,
+            background: self.background,
+            image: self.loaded_image.as_ref(),
+            strokes: &self.strokes,
+        }
+    }
+
+    /// A snapshot of the drawing for saving as a session.
+    pub fn session(&self) -> Session {
+        Session {
+            background: self.background,
+            image: self.loaded_image.clone(),
+            strokes: self.strokes.clone(),
+            redo_stack: self.redo_stack.clone(),
+        }
+    }
+
+    /// Redraws every output on its next chance, e.g. because the active stroke changed.
+    /// Outputs the change doesn't reach skip the commit.
+    pub fn request_redraw(&mut self) {
+        for surface in &mut self.surfaces {
+            surface.needs_redraw = true;
+        }
+    }
+
+    /// Draws every surface that has changes and isn't waiting on a frame callback.
+    pub fn redraw(&mut self, qh: &QueueHandle<Self>) {
+        for surface in &mut self.surfaces {
+            if surface.configured && surface.needs_redraw && !surface.frame_pending {
+                // While a screenshot is being taken its output is kept blank
+                let hidden = self
+                    .capture
+                    .as_ref()
+                    .is_some_and(|capture| *capture.output() == surface.output);
+                surface.draw(
+                    qh,
+                    &mut self.pool,
+                    buffer_format(&self.shm),
+                    self.active_stroke.as_ref(),
+                    hidden,
+                );
+                // Only the callback of a frame committed from here on shows the blank overlay
+                if hidden && surface.frame_pending {
+                    if let Some(Capture::Hiding { blanked, .. }) = &mut self.capture {
+                        *blanked = true;
+                    }
                 }
             }
+        }
+    }
 
-            {
-                let mut pixmap = tiny_skia::PixmapMut::from_bytes(canvas, width, height).unwrap();
-                // 3. Render the active stroke on top (it inherently clips if handled correctly by skia, or it falls within dirty bounds)
-                if let Some(active) = &self.active_stroke {
-                    render_stroke(&mut pixmap, active);
-                }
+    fn surface_index(&self, surface: &wl_surface::WlSurface) -> Option<usize> {
+        self.surfaces
+            .iter()
+            .position(|s| s.layer.wl_surface() == surface)
+    }
+
+    /// Drops the overlays matching `predicate`, e.g. because their output was unplugged.
+    /// Without any overlay left there is nothing to draw on, so we exit.
+    fn remove_surfaces(&mut self, predicate: impl Fn(&OutputSurface) -> bool) {
+        let count = self.surfaces.len();
+        self.surfaces.retain(|surface| !predicate(surface));
+        if self.surfaces.len() < count && self.surfaces.is_empty() {
+            log::info!("No outputs left to draw on");
+            self.exit = true;
+        }
+    }
+
+    /// Swaps in a freshly loaded config, resetting the current tool settings to its
+    /// defaults while keeping the drawing intact.
+    pub fn apply_config(&mut self, qh: &QueueHandle<Self>, config: Config) {
+        self.color = config.tool.color.0;
+        self.thickness = config.tool.thickness;
+        for surface in &mut self.surfaces {
+            surface.layer.set_layer(config.ui.layer.into());
+            surface
+                .layer
+                .set_keyboard_interactivity(config.ui.keyboard_interactivity.into());
+            // Committed along with the next frame, whose buffer matches the surface's
+            // current scale and transform
+            surface.needs_commit = true;
+            surface.needs_redraw = true;
+        }
+        self.config = config;
+        self.update_cursor();
+        self.redraw(qh);
+    }
+
+    /// Takes the overlays off screen without tearing down the connection, e.g. to keep
+    /// serving the clipboard after the user is done drawing.
+    pub fn unmap(&mut self) {
+        for surface in &mut self.surfaces {
+            surface.unmap();
+        }
+    }
+
+    /// Sets the pointer cursor to a preview of the current brush, or a crosshair for shape
+    /// tools. Must be called again whenever the tool, color or thickness changes so the
+    /// preview stays in sync.
+    pub fn update_cursor(&mut self) {
+        let (Some(pointer), Some(serial)) = (&self.pointer, self.pointer_enter_serial) else {
+            return;
+        };
+
+        if self.tool.is_shape() {
+            if let Some(device) = &self.cursor_shape_device {
+                device.set_shape(serial, Shape::Crosshair);
+                return;
             }
+        }
 
-            // 4. Convert RGBA to BGRA only in the dirty region
-            for y in dirty.y..(dirty.y + dirty.h as i32) {
-                let y = y as usize;
-                let start = (y * width as usize + dirty.x as usize) * 4;
-                let len = (dirty.w as usize) * 4;
-                if start + len <= canvas.len() {
-                    for chunk in canvas[start..start + len].chunks_exact_mut(4) {
-                        chunk.swap(0, 2);
-                    }
-                }
+        // Cursor surfaces only take integer buffer scales, so fractionally scaled outputs get
+        // the next larger one and the compositor scales it down
+        let scale = self
+            .surfaces
+            .iter()
+            .find(|s| self.pointer_output.as_ref() == Some(&s.output))
+            .map_or(1, |s| s.scale.ceil().max(1.0) as u32);
+
+        // Leave room for the outline and anti-aliasing around the disc
+        let size = (self.thickness.max(2.0).ceil() as u32 + 4) | 1;
+        let buffer_size = size * scale;
+        let stride = buffer_size as i32 * 4;
+        let format = buffer_format(&self.shm);
+        let (buffer, canvas) = match self.cursor_pool.create_buffer(
+            buffer_size as i32,
+            buffer_size as i32,
+            stride,
+            format,
+        ) {
+            Ok(b) => b,
+            Err(e) => {
+                log::warn!("Failed to create cursor buffer: {}", e);
+                return;
             }
+        };
 
-            self.layer
-                .wl_surface()
-                .damage_buffer(dirty.x, dirty.y, dirty.w as i32, dirty.h as i32);
-            self.layer
-                .wl_surface()
-                .frame(qh, self.layer.wl_surface().clone());
-            self.frame_pending = true;
-            buffer
-                .attach_to(self.layer.wl_surface())
-                .expect("buffer attach");
-            self.layer.commit();
-            self.needs_redraw = false;
-        } else {
-            // Nothing to draw
-            // We just don't commit a new buffer or a new frame callback!
-            // This halts the loop until `needs_redraw` becomes true again.
+        canvas.fill(0);
+        {
+            let mut pixmap =
+                tiny_skia::PixmapMut::from_bytes(canvas, buffer_size, buffer_size).unwrap();
+            render_brush_preview(
+                &mut pixmap,
+                self.color,
+                self.thickness * scale as f32,
+                scale as f32,
+            );
         }
+        if format != wl_shm::Format::Abgr8888 {
+            rgba_to_bgra(canvas);
+        }
+
+        let hotspot = (size / 2) as i32;
+        pointer.set_cursor(serial, Some(&self.cursor_surface), hotspot, hotspot);
+        self.cursor_surface.set_buffer_scale(scale as i32);
+        self.cursor_surface
+            .damage_buffer(0, 0, buffer_size as i32, buffer_size as i32);
+        buffer
+            .attach_to(&self.cursor_surface)
+            .expect("buffer attach");
+        self.cursor_surface.commit();
     }
 }
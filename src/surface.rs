@@ -0,0 +1,662 @@
+use smithay_client_toolkit::reexports::protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+};
+use smithay_client_toolkit::{
+    compositor::CompositorState,
+    output::OutputInfo,
+    shell::{
+        wlr_layer::{Anchor, LayerShell, LayerSurface},
+        WaylandSurface,
+    },
+    shm::{
+        slot::{Buffer, SlotPool},
+        Shm,
+    },
+};
+use wayland_client::{
+    protocol::{wl_output, wl_shm},
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+
+use crate::config::Config;
+use crate::draw::{render_stroke_transformed, rgba_to_bgra};
+use crate::state::AppState;
+use crate::types::{Rect, Region, Stroke};
+
+/// The overlay on one output: its layer surface, and the canvas and damage state needed
+/// to redraw it. Strokes live in the compositor's global (logical) coordinate space and
+/// every surface shows the part of the drawing that falls on its output.
+pub struct OutputSurface {
+    pub output: wl_output::WlOutput,
+    pub layer: LayerSurface,
+    /// Where the output's top-left corner sits in global coordinates.
+    pub position: (i32, i32),
+    /// Size in logical (surface) pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Buffer pixels per logical pixel, so lines stay sharp on HiDPI outputs.
+    pub scale: f64,
+    /// The output's rotation and flip, which buffers are rendered with.
+    pub buffer_transform: wl_output::Transform,
+    pub configured: bool,
+    /// Set when the compositor supports fractional scaling, which then takes precedence
+    /// over the integer scale of the output.
+    pub fractional_scale: Option<WpFractionalScaleV1>,
+    pub viewport: Option<WpViewport>,
+
+    /// Background, loaded drawing and finished strokes, in buffer coordinates. Missing
+    /// until the first configure, or if the output is too large to allocate one.
+    pub completed_canvas: Option<tiny_skia::Pixmap>,
+    /// The completed canvas with the active stroke drawn on top, which frames are copied
+    /// from. A growing stroke only has its new segments drawn onto it.
+    pub working_canvas: Option<tiny_skia::Pixmap>,
+    /// How many of the active stroke's points are on the working canvas, and the area
+    /// they cover, to be restored from the completed canvas once the stroke changes as a
+    /// whole or ends.
+    pub active_points_drawn: usize,
+    pub active_stroke_rect: Option<Rect>,
+    /// Damage, in buffer coordinates.
+    pub pending_damage: Region,
+    pub needs_redraw: bool,
+    /// Set when surface state, such as the layer, waits for a commit even without damage.
+    pub needs_commit: bool,
+    pub frame_pending: bool,
+    /// Buffers are reused once the compositor releases them, so only the parts that
+    /// changed since each was last drawn need repainting.
+    pub buffers: Vec<SwapBuffer>,
+}
+
+/// A buffer of the swapchain.
+pub struct SwapBuffer {
+    buffer: Buffer,
+    /// What changed since this buffer was last drawn (its age, as damage), in buffer
+    /// coordinates.
+    stale: Region,
+}
+
+impl OutputSurface {
+    /// Creates a full-screen layer surface on `output` and commits it so the compositor
+    /// sends the first configure.
+    pub fn new(
+        qh: &QueueHandle<AppState>,
+        compositor: &CompositorState,
+        layer_shell: &LayerShell,
+        output: wl_output::WlOutput,
+        info: &OutputInfo,
+        config: &Config,
+    ) -> OutputSurface {
+        let surface = compositor.create_surface(qh);
+        let layer = layer_shell.create_layer_surface(
+            qh,
+            surface,
+            config.ui.layer.into(),
+            Some("sway-draw"),
+            Some(&output),
+        );
+        layer.set_anchor(Anchor::TOP | Anchor::BOTTOM | Anchor::LEFT | Anchor::RIGHT);
+        layer.set_keyboard_interactivity(config.ui.keyboard_interactivity.into());
+        layer.set_exclusive_zone(-1); // Don't move other windows
+
+        // Commit to get the configure event
+        layer.commit();
+
+        // The output's size is only a first guess; the configure has the final word
+        let (width, height) = info
+            .logical_size
+            .map_or((0, 0), |(w, h)| (w.max(0) as u32, h.max(0) as u32));
+        OutputSurface {
+            output,
+            layer,
+            position: logical_position(info),
+            width,
+            height,
+            scale: 1.0,
+            buffer_transform: wl_output::Transform::Normal,
+            configured: false,
+            fractional_scale: None,
+            viewport: None,
+            completed_canvas: None,
+            working_canvas: None,
+            active_points_drawn: 0,
+            active_stroke_rect: None,
+            pending_damage: Region::default(),
+            needs_redraw: true,
+            needs_commit: false,
+            frame_pending: false,
+            buffers: Vec::new(),
+        }
+    }
+
+    /// Renders at the compositor's preferred fractional scale through a viewport, which
+    /// maps the larger buffer back onto the logical surface size.
+    pub fn enable_fractional_scale(
+        &mut self,
+        qh: &QueueHandle<AppState>,
+        manager: &WpFractionalScaleManagerV1,
+        viewporter: &WpViewporter,
+    ) {
+        let surface = self.layer.wl_surface();
+        self.fractional_scale = Some(manager.get_fractional_scale(surface, qh, ()));
+        self.viewport = Some(viewporter.get_viewport(surface, qh, ()));
+    }
+
+    /// The surface size in buffer pixels, before the output transform.
+    fn scaled_size(&self) -> (u32, u32) {
+        (
+            (self.width as f64 * self.scale).round() as u32,
+            (self.height as f64 * self.scale).round() as u32,
+        )
+    }
+
+    /// The size of the buffers we attach, which are rotated along with the output.
+    pub fn buffer_size(&self) -> (u32, u32) {
+        let (width, height) = self.scaled_size();
+        match self.buffer_transform {
+            wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270 => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    /// Re-creates the completed canvas at the current size, scale and transform. The
+    /// canvas is rebuilt from the strokes rather than copied over, so a resize (e.g. an
+    /// output mode change) keeps the whole drawing at its place in global coordinates.
+    pub fn reallocate(
+        &mut self,
+        background: tiny_skia::Color,
+        image: Option<&tiny_skia::Pixmap>,
+        strokes: &[Stroke],
+    ) {
+        match &self.viewport {
+            Some(viewport) => viewport.set_destination(self.width as i32, self.height as i32),
+            None => self.layer.wl_surface().set_buffer_scale(self.scale as i32),
+        }
+        // Buffers are rendered already transformed, so the compositor can show them as is
+        self.layer
+            .wl_surface()
+            .set_buffer_transform(self.buffer_transform);
+        let (width, height) = self.buffer_size();
+        self.completed_canvas = tiny_skia::Pixmap::new(width, height);
+        self.working_canvas = tiny_skia::Pixmap::new(width, height);
+        if self.completed_canvas.is_none() || self.working_canvas.is_none() {
+            log::error!(
+                "Cannot allocate a {}x{} canvas for the output",
+                width,
+                height
+            );
+        }
+        // Damage so far is in the old buffer's coordinates, and the rebuild covers it all
+        self.pending_damage = Region::default();
+        self.active_points_drawn = 0;
+        self.active_stroke_rect = None;
+        self.buffers.clear();
+        self.rerender(background, image, strokes);
+    }
+
+    /// The area of the output, in global coordinates.
+    pub fn bounds(&self) -> Rect {
+        Rect {
+            x: self.position.0,
+            y: self.position.1,
+            w: self.width,
+            h: self.height,
+        }
+    }
+
+    /// Maps global coordinates to buffer coordinates.
+    pub fn transform(&self) -> tiny_skia::Transform {
+        let (width, height) = self.scaled_size();
+        tiny_skia::Transform::from_scale(self.scale as f32, self.scale as f32)
+            .pre_translate(-self.position.0 as f32, -self.position.1 as f32)
+            .post_concat(output_transform(
+                self.buffer_transform,
+                width as f32,
+                height as f32,
+            ))
+    }
+
+    /// The buffer pixels covering a rectangle in global coordinates, if any are on this
+    /// output.
+    fn to_buffer(&self, rect: &Rect) -> Option<Rect> {
+        let rect = rect.intersect(&self.bounds())?;
+        let rect =
+            tiny_skia::Rect::from_xywh(rect.x as f32, rect.y as f32, rect.w as f32, rect.h as f32)?
+                .transform(self.transform())?
+                .round_out()?;
+        Some(Rect {
+            x: rect.x(),
+            y: rect.y(),
+            w: rect.width(),
+            h: rect.height(),
+        })
+    }
+
+    /// Marks a region, in global coordinates, for redrawing if it overlaps this output.
+    pub fn add_damage(&mut self, rect: &Rect) {
+        let Some(rect) = self.to_buffer(rect) else {
+            return;
+        };
+        self.pending_damage.add(rect);
+        self.needs_redraw = true;
+    }
+
+    /// Rebuilds the completed canvas from scratch, e.g. after strokes were removed.
+    pub fn rerender(
+        &mut self,
+        background: tiny_skia::Color,
+        image: Option<&tiny_skia::Pixmap>,
+        strokes: &[Stroke],
+    ) {
+        let transform = self.transform();
+        let Some(canvas) = &mut self.completed_canvas else {
+            return;
+        };
+        canvas.fill(background);
+        if let Some(image) = image {
+            let paint = tiny_skia::PixmapPaint {
+                quality: tiny_skia::FilterQuality::Bilinear,
+                ..Default::default()
+            };
+            canvas.draw_pixmap(0, 0, image.as_ref(), &paint, transform, None);
+        }
+        for stroke in strokes {
+            render_stroke_transformed(&mut canvas.as_mut(), stroke, transform);
+        }
+        self.add_damage(&self.bounds());
+    }
+
+    /// Renders a finished stroke into the completed canvas.
+    pub fn bake(&mut self, stroke: &Stroke) {
+        // The next active stroke is a different one, which starts from scratch
+        self.active_points_drawn = 0;
+        let transform = self.transform();
+        if let (Some(canvas), Some(bounds)) = (&mut self.completed_canvas, stroke.bounding_box()) {
+            render_stroke_transformed(&mut canvas.as_mut(), stroke, transform);
+            self.add_damage(&bounds);
+        }
+    }
+
+    /// Takes the overlay off screen without destroying the surface.
+    pub fn unmap(&mut self) {
+        self.layer.wl_surface().attach(None, 0, 0);
+        self.layer.commit();
+    }
+
+    /// Submits a new frame for whatever changed since the last one, in buffers of the given
+    /// `format` (see [`buffer_format`]). `hidden` blanks the surface instead, e.g. while a
+    /// screenshot of its output is taken.
+    pub fn draw(
+        &mut self,
+        qh: &QueueHandle<AppState>,
+        pool: &mut SlotPool,
+        format: wl_shm::Format,
+        active_stroke: Option<&Stroke>,
+        hidden: bool,
+    ) {
+        let (width, height) = match &self.completed_canvas {
+            Some(canvas) => (canvas.width(), canvas.height()),
+            None => {
+                self.needs_redraw = false;
+                return;
+            }
+        };
+        let stride = width as i32 * 4;
+        let screen_bound = Rect {
+            x: 0,
+            y: 0,
+            w: width,
+            h: height,
+        };
+        let transform = self.transform();
+
+        // 1. Calculate what to restore on the working canvas from the completed one, and
+        // which part of the active stroke to draw over it
+        let mut restore = std::mem::take(&mut self.pending_damage);
+        let mut active_rect = self.active_stroke_rect.take();
+        // A stroke that only grew since the last frame just gets its new segments, unless
+        // the completed canvas changed underneath it
+        let incremental = active_stroke
+            .is_some_and(|s| self.active_points_drawn > 0 && s.draws_incrementally())
+            && !active_rect.as_ref().is_some_and(|r| restore.intersects(r));
+        let tail;
+        let render = match active_stroke {
+            Some(stroke) if incremental => {
+                tail = stroke.tail(self.active_points_drawn);
+                (stroke.points.len() > self.active_points_drawn).then_some(&tail)
+            }
+            _ => {
+                // Erase last frame's version of the stroke and draw all of it anew
+                if let Some(r) = active_rect.take() {
+                    restore.add(r);
+                }
+                active_stroke
+            }
+        };
+        self.active_points_drawn = active_stroke.map_or(0, |s| s.points.len());
+        // As far as it falls on this output
+        let render_rect = render
+            .and_then(|s| s.bounding_box())
+            .and_then(|r| self.to_buffer(&r));
+
+        // Constrain the damage to the actual window bounds
+        let restore = restore.clip(&screen_bound);
+        let mut dirty = restore.clone();
+        if let Some(r) = &render_rect {
+            dirty.add(r.clone());
+            active_rect = Some(match active_rect {
+                Some(active) => active.union(r),
+                None => r.clone(),
+            });
+        }
+        let dirty = dirty.clip(&screen_bound);
+        self.active_stroke_rect = active_rect;
+
+        let (Some(completed_canvas), Some(working_canvas)) =
+            (&self.completed_canvas, &mut self.working_canvas)
+        else {
+            self.needs_redraw = false;
+            return;
+        };
+        copy_region(
+            completed_canvas.data(),
+            working_canvas.data_mut(),
+            width,
+            &restore,
+        );
+        if let (Some(stroke), Some(_)) = (render, &render_rect) {
+            render_stroke_transformed(&mut working_canvas.as_mut(), stroke, transform);
+        }
+
+        if dirty.is_empty() && !self.needs_commit {
+            // Nothing to draw: without a new buffer or frame callback the loop halts
+            // until there is damage again
+            self.needs_redraw = false;
+            return;
+        }
+
+        // 2. Pick a buffer the compositor is done with, growing the swapchain if it
+        // still holds all of them
+        let index = match self
+            .buffers
+            .iter()
+            .position(|b| b.buffer.canvas(pool).is_some())
+        {
+            Some(index) => index,
+            None => match pool.create_buffer(width as i32, height as i32, stride, format) {
+                Ok((buffer, _)) => {
+                    if self.buffers.len() >= 3 {
+                        log::debug!("Growing swapchain to {} buffers", self.buffers.len() + 1);
+                    }
+                    // A new buffer's contents are undefined, so it is stale everywhere
+                    let mut stale = Region::default();
+                    stale.add(screen_bound.clone());
+                    self.buffers.push(SwapBuffer { buffer, stale });
+                    self.buffers.len() - 1
+                }
+                Err(e) => {
+                    log::error!("Failed to create a {}x{} buffer: {}", width, height, e);
+                    // Keep the damage for the next attempt
+                    self.pending_damage = dirty;
+                    self.needs_redraw = false;
+                    return;
+                }
+            },
+        };
+
+        // Every other buffer now lags behind by this frame's damage, while the chosen one
+        // catches up on everything it missed since it was last drawn
+        for (i, b) in self.buffers.iter_mut().enumerate() {
+            if i != index {
+                b.stale.add_region(&dirty);
+            }
+        }
+        let mut repaint = std::mem::take(&mut self.buffers[index].stale);
+        repaint.add_region(&dirty);
+        let buffer = &self.buffers[index].buffer;
+        let Some(canvas) = buffer.canvas(pool) else {
+            return;
+        };
+
+        // 3. Copy only the repainted parts of the working canvas into our wayland buffer
+        if hidden {
+            for rect in repaint.rects() {
+                for y in rect.y..(rect.y + rect.h as i32) {
+                    let start = (y as usize * width as usize + rect.x as usize) * 4;
+                    let len = (rect.w as usize) * 4;
+                    if start + len <= canvas.len() {
+                        canvas[start..start + len].fill(0);
+                    }
+                }
+            }
+        } else {
+            copy_region(working_canvas.data(), canvas, width, &repaint);
+        }
+
+        // 4. Unless the compositor takes RGBA as is, convert to BGRA only in the repainted
+        // region, whose rectangles are disjoint so no pixel gets swapped twice
+        if format != wl_shm::Format::Abgr8888 {
+            for rect in repaint.rects() {
+                for y in rect.y..(rect.y + rect.h as i32) {
+                    let start = (y as usize * width as usize + rect.x as usize) * 4;
+                    let len = (rect.w as usize) * 4;
+                    if start + len <= canvas.len() {
+                        rgba_to_bgra(&mut canvas[start..start + len]);
+                    }
+                }
+            }
+        }
+
+        // The compositor already has the previous frame, so only this frame's changes
+        // are damage to it
+        for rect in dirty.rects() {
+            self.layer
+                .wl_surface()
+                .damage_buffer(rect.x, rect.y, rect.w as i32, rect.h as i32);
+        }
+        self.layer
+            .wl_surface()
+            .frame(qh, self.layer.wl_surface().clone());
+        self.frame_pending = true;
+        buffer
+            .attach_to(self.layer.wl_surface())
+            .expect("buffer attach");
+        self.layer.commit();
+        self.needs_commit = false;
+        self.needs_redraw = false;
+    }
+}
+
+/// Where an output sits in global coordinates. Without xdg-output, wl_output's own
+/// position is the best we have.
+pub fn logical_position(info: &OutputInfo) -> (i32, i32) {
+    info.logical_position.unwrap_or(info.location)
+}
+
+/// The shm format to hand buffers to the compositor in. tiny-skia renders RGBA, which
+/// wl_shm calls `Abgr8888`; only `Argb8888` is guaranteed to be supported, though, and
+/// needs red and blue swapped first.
+pub fn buffer_format(shm: &Shm) -> wl_shm::Format {
+    if shm.formats().contains(&wl_shm::Format::Abgr8888) {
+        wl_shm::Format::Abgr8888
+    } else {
+        wl_shm::Format::Argb8888
+    }
+}
+
+/// Copies the pixels within `region` between two canvases `width` pixels wide.
+fn copy_region(from: &[u8], to: &mut [u8], width: u32, region: &Region) {
+    for rect in region.rects() {
+        for y in rect.y..(rect.y + rect.h as i32) {
+            let start = (y as usize * width as usize + rect.x as usize) * 4;
+            let len = (rect.w as usize) * 4;
+            if start + len <= from.len().min(to.len()) {
+                to[start..start + len].copy_from_slice(&from[start..start + len]);
+            }
+        }
+    }
+}
+
+/// Maps a `width` x `height` image onto a buffer pre-transformed for an output with the
+/// given transform, as `wl_surface.set_buffer_transform` describes it: e.g. `_90` turns
+/// the image 90 degrees counter-clockwise into a `height` x `width` buffer.
+pub fn output_transform(
+    transform: wl_output::Transform,
+    width: f32,
+    height: f32,
+) -> tiny_skia::Transform {
+    use wl_output::Transform;
+
+    // x' = sx * x + kx * y + tx, y' = ky * x + sy * y + ty
+    let (sx, ky, kx, sy, tx, ty) = match transform {
+        Transform::_90 => (0.0, -1.0, 1.0, 0.0, 0.0, width),
+        Transform::_180 => (-1.0, 0.0, 0.0, -1.0, width, height),
+        Transform::_270 => (0.0, 1.0, -1.0, 0.0, height, 0.0),
+        Transform::Flipped => (-1.0, 0.0, 0.0, 1.0, width, 0.0),
+        Transform::Flipped90 => (0.0, 1.0, 1.0, 0.0, 0.0, 0.0),
+        Transform::Flipped180 => (1.0, 0.0, 0.0, -1.0, 0.0, height),
+        Transform::Flipped270 => (0.0, -1.0, -1.0, 0.0, height, width),
+        _ => (1.0, 0.0, 0.0, 1.0, 0.0, 0.0),
+    };
+    tiny_skia::Transform::from_row(sx, ky, kx, sy, tx, ty)
+}
+
+impl Drop for OutputSurface {
+    fn drop(&mut self) {
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        // The scale is sent in 120ths
+        let scale = scale as f64 / 120.0;
+        let Some(surface) = state
+            .surfaces
+            .iter_mut()
+            .find(|s| s.fractional_scale.as_ref() == Some(proxy))
+        else {
+            return;
+        };
+        if surface.scale != scale {
+            surface.scale = scale;
+            surface.reallocate(
+                state.background,
+                state.loaded_image.as_ref(),
+                &state.strokes,
+            );
+            state.update_cursor();
+            state.redraw(qh);
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for AppState {
+    fn event(
+        _: &mut Self,
+        _: &WpFractionalScaleManagerV1,
+        _: <WpFractionalScaleManagerV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // wp_fractional_scale_manager_v1 has no events
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for AppState {
+    fn event(
+        _: &mut Self,
+        _: &WpViewporter,
+        _: <WpViewporter as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // wp_viewporter has no events
+    }
+}
+
+impl Dispatch<WpViewport, ()> for AppState {
+    fn event(
+        _: &mut Self,
+        _: &WpViewport,
+        _: <WpViewport as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // wp_viewport has no events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where weston's `weston_transformed_coord` puts a point of a `width` x `height`
+    /// surface in the buffer.
+    fn weston(transform: wl_output::Transform, w: f32, h: f32, x: f32, y: f32) -> (f32, f32) {
+        use wl_output::Transform;
+        match transform {
+            Transform::_90 => (y, w - x),
+            Transform::_180 => (w - x, h - y),
+            Transform::_270 => (h - y, x),
+            Transform::Flipped => (w - x, y),
+            Transform::Flipped90 => (y, x),
+            Transform::Flipped180 => (x, h - y),
+            Transform::Flipped270 => (h - y, w - x),
+            _ => (x, y),
+        }
+    }
+
+    #[test]
+    fn output_transform_matches_weston() {
+        use wl_output::Transform;
+        let (w, h) = (100.0, 50.0);
+        for transform in [
+            Transform::Normal,
+            Transform::_90,
+            Transform::_180,
+            Transform::_270,
+            Transform::Flipped,
+            Transform::Flipped90,
+            Transform::Flipped180,
+            Transform::Flipped270,
+        ] {
+            let matrix = output_transform(transform, w, h);
+            for (x, y) in [(0.0, 0.0), (w, h), (10.0, 20.0), (w, 0.0), (0.0, h)] {
+                let mut point = tiny_skia::Point::from_xy(x, y);
+                matrix.map_point(&mut point);
+                assert_eq!(
+                    (point.x, point.y),
+                    weston(transform, w, h, x, y),
+                    "{:?} of ({}, {})",
+                    transform,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use base64::Engine;
+
+use crate::config::HexColor;
+use crate::export::Scene;
+use crate::types::{Stroke, Tool};
+
+/// Serializes the scene as an SVG document. Strokes become vector elements styled the same
+/// way `draw::render_stroke_transformed` rasterizes them (round caps and joins); a drawing
+/// loaded from a PNG is embedded as an image underneath them.
+pub fn to_svg(scene: &Scene) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="{x} {y} {w} {h}">"#,
+        x = scene.x,
+        y = scene.y,
+        w = scene.width,
+        h = scene.height
+    );
+
+    if scene.background.alpha() > 0.0 {
+        let _ = writeln!(
+            svg,
+            r#"  <rect x="{}" y="{}" width="{}" height="{}" fill="{}"{}/>"#,
+            scene.x,
+            scene.y,
+            scene.width,
+            scene.height,
+            HexColor(scene.background).rgb(),
+            opacity("fill-opacity", scene.background)
+        );
+    }
+    if let Some(png) = scene.image.and_then(|image| image.encode_png().ok()) {
+        let _ = writeln!(
+            svg,
+            r#"  <image width="{}" height="{}" href="data:image/png;base64,{}"/>"#,
+            scene.image.map_or(0, |image| image.width()),
+            scene.image.map_or(0, |image| image.height()),
+            base64::engine::general_purpose::STANDARD.encode(png)
+        );
+    }
+
+    for stroke in scene.strokes {
+        // Mirrors render_stroke_transformed, which skips strokes that never left their first point
+        if stroke.points.len() < 2 {
+            continue;
+        }
+        let Some(element) = element(stroke) else {
+            continue;
+        };
+        let _ = writeln!(
+            svg,
+            r#"  {} fill="none" stroke="{}"{} stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"/>"#,
+            element,
+            HexColor(stroke.color).rgb(),
+            opacity("stroke-opacity", stroke.color),
+            num(stroke.thickness)
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// The opening of the SVG element for a stroke's geometry, without its styling.
+fn element(stroke: &Stroke) -> Option<String> {
+    let first = stroke.points.first()?;
+    let last = stroke.points.last()?;
+    let (l, t, r, b) = stroke.shape_ltrb()?;
+    Some(match stroke.tool {
+        Tool::Pen => {
+            let mut d = format!("M{} {}", num(first.x), num(first.y));
+            for p in &stroke.points[1..] {
+                let _ = write!(d, " L{} {}", num(p.x), num(p.y));
+            }
+            format!(r#"<path d="{}""#, d)
+        }
+        Tool::Line => format!(
+            r#"<line x1="{}" y1="{}" x2="{}" y2="{}""#,
+            num(first.x),
+            num(first.y),
+            num(last.x),
+            num(last.y)
+        ),
+        Tool::Rectangle => format!(
+            r#"<rect x="{}" y="{}" width="{}" height="{}""#,
+            num(l),
+            num(t),
+            num(r - l),
+            num(b - t)
+        ),
+        Tool::Ellipse => format!(
+            r#"<ellipse cx="{}" cy="{}" rx="{}" ry="{}""#,
+            num((l + r) / 2.0),
+            num((t + b) / 2.0),
+            num((r - l) / 2.0),
+            num((b - t) / 2.0)
+        ),
+        Tool::Arrow => {
+            let (left, right) = stroke.arrow_head()?;
+            format!(
+                r#"<path d="M{} {} L{} {} M{} {} L{} {} L{} {}""#,
+                num(first.x),
+                num(first.y),
+                num(last.x),
+                num(last.y),
+                num(left.x),
+                num(left.y),
+                num(last.x),
+                num(last.y),
+                num(right.x),
+                num(right.y)
+            )
+        }
+    })
+}
+
+/// An opacity attribute for translucent colors; opaque ones don't need one.
+fn opacity(attribute: &str, color: tiny_skia::Color) -> String {
+    if color.alpha() < 1.0 {
+        format!(r#" {}="{}""#, attribute, num(color.alpha()))
+    } else {
+        String::new()
+    }
+}
+
+/// Formats a coordinate with at most two decimals, which is plenty at screen resolution.
+fn num(v: f32) -> String {
+    format!("{}", (v * 100.0).round() / 100.0)
+}
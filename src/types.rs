@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Clone, Debug)]
 pub struct Point {
     pub x: f32,
@@ -27,6 +29,10 @@ impl Rect {
         }
     }
 
+    pub fn area(&self) -> u64 {
+        self.w as u64 * self.h as u64
+    }
+
     pub fn intersect(&self, other: &Rect) -> Option<Rect> {
         let max_x = std::cmp::min(self.x + self.w as i32, other.x + other.w as i32);
         let max_y = std::cmp::min(self.y + self.h as i32, other.y + other.h as i32);
@@ -46,14 +52,194 @@ impl Rect {
     }
 }
 
+/// Beyond this many rectangles a region merges new ones into their closest neighbor, as
+/// the per-rectangle overhead starts to outweigh the pixels saved.
+const MAX_REGION_RECTS: usize = 8;
+
+/// A set of disjoint rectangles, e.g. the damaged parts of a surface. Rectangles that
+/// overlap, or whose bounding box wastes little over drawing both, are merged as they come
+/// in, so strokes in opposite corners stay two small rectangles instead of one spanning
+/// the screen.
+#[derive(Clone, Debug, Default)]
+pub struct Region {
+    rects: Vec<Rect>,
+}
+
+impl Region {
+    pub fn add(&mut self, mut rect: Rect) {
+        if rect.w == 0 || rect.h == 0 {
+            return;
+        }
+        // Overlapping rectangles are always merged, which keeps them disjoint
+        while let Some(i) = self.rects.iter().position(|r| {
+            r.intersect(&rect).is_some() || r.union(&rect).area() <= r.area() + rect.area()
+        }) {
+            rect = rect.union(&self.rects.swap_remove(i));
+        }
+        if self.rects.len() >= MAX_REGION_RECTS {
+            let closest = (0..self.rects.len())
+                .min_by_key(|&i| self.rects[i].union(&rect).area() - self.rects[i].area())
+                .unwrap_or(0);
+            let merged = self.rects.swap_remove(closest).union(&rect);
+            // The merged rectangle may now overlap others
+            self.add(merged);
+            return;
+        }
+        self.rects.push(rect);
+    }
+
+    pub fn add_region(&mut self, other: &Region) {
+        for rect in &other.rects {
+            self.add(rect.clone());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn intersects(&self, rect: &Rect) -> bool {
+        self.rects.iter().any(|r| r.intersect(rect).is_some())
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    /// The part of the region within `bounds`.
+    pub fn clip(&self, bounds: &Rect) -> Region {
+        Region {
+            rects: self
+                .rects
+                .iter()
+                .filter_map(|r| r.intersect(bounds))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tool {
+    Pen,
+    Line,
+    Rectangle,
+    Ellipse,
+    Arrow,
+}
+
+impl Tool {
+    /// Shape tools are defined by their first and last point only.
+    pub fn is_shape(self) -> bool {
+        self != Tool::Pen
+    }
+
+    /// The name used for the tool in config files, on the command line and in sessions.
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Pen => "pen",
+            Tool::Line => "line",
+            Tool::Rectangle => "rectangle",
+            Tool::Ellipse => "ellipse",
+            Tool::Arrow => "arrow",
+        }
+    }
+}
+
+impl std::str::FromStr for Tool {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pen" => Ok(Tool::Pen),
+            "line" => Ok(Tool::Line),
+            "rectangle" | "rect" => Ok(Tool::Rectangle),
+            "ellipse" => Ok(Tool::Ellipse),
+            "arrow" => Ok(Tool::Arrow),
+            _ => Err(format!(
+                "unknown tool {:?}, expected pen, line, rectangle, ellipse or arrow",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Stroke {
+    pub tool: Tool,
     pub points: Vec<Point>,
+    /// Compositor timestamp (in milliseconds) of each point, used to animate how the
+    /// stroke was drawn. Empty for strokes whose timing is unknown.
+    pub times: Vec<u32>,
     pub color: tiny_skia::Color,
     pub thickness: f32,
 }
 
 impl Stroke {
+    /// Adds a point under the pointer. Freehand strokes keep every point, while shapes
+    /// only track the point they were started at and the current one.
+    pub fn extend(&mut self, point: Point, time: u32) {
+        if self.tool.is_shape() && self.points.len() > 1 {
+            self.points.pop();
+            self.times.pop();
+        }
+        // Keep the timestamps in order even if the compositor's clock wraps mid-stroke
+        let time = self.times.last().map_or(time, |&last| time.max(last));
+        self.points.push(point);
+        self.times.push(time);
+    }
+
+    /// The box spanned by a shape's first and last point, as left, top, right, bottom.
+    pub fn shape_ltrb(&self) -> Option<(f32, f32, f32, f32)> {
+        let first = self.points.first()?;
+        let last = self.points.last()?;
+        Some((
+            first.x.min(last.x),
+            first.y.min(last.y),
+            first.x.max(last.x),
+            first.y.max(last.y),
+        ))
+    }
+
+    /// Whether the stroke, while it is drawn, can be extended by rendering just its new
+    /// segments over the old ones. Shapes change as a whole, and a translucent stroke would
+    /// be blended twice where its segments meet.
+    pub fn draws_incrementally(&self) -> bool {
+        self.tool == Tool::Pen && self.color.is_opaque()
+    }
+
+    /// The segments of a freehand stroke from its `from`th point on, which join up with the
+    /// ones before.
+    pub fn tail(&self, from: usize) -> Stroke {
+        let start = from.saturating_sub(1).min(self.points.len());
+        Stroke {
+            tool: self.tool,
+            points: self.points[start..].to_vec(),
+            times: Vec::new(),
+            color: self.color,
+            thickness: self.thickness,
+        }
+    }
+
+    pub fn arrow_head_length(&self) -> f32 {
+        (self.thickness * 3.0).max(12.0)
+    }
+
+    /// The two barb endpoints of an arrow head at the stroke's last point.
+    pub fn arrow_head(&self) -> Option<(Point, Point)> {
+        let [.., from, tip] = self.points.as_slice() else {
+            return None;
+        };
+        let angle = (tip.y - from.y).atan2(tip.x - from.x);
+        let len = self.arrow_head_length();
+        let spread = std::f32::consts::FRAC_PI_6;
+        let barb = |a: f32| Point {
+            x: tip.x - len * a.cos(),
+            y: tip.y - len * a.sin(),
+        };
+        Some((barb(angle - spread), barb(angle + spread)))
+    }
+
     pub fn bounding_box(&self) -> Option<Rect> {
         if self.points.is_empty() {
             return None;
@@ -72,7 +258,11 @@ impl Stroke {
         }
 
         // Pad by thickness
-        let pad = self.thickness / 2.0 + 2.0; // slight extra padding for anti-aliasing edge cases
+        let mut pad = self.thickness / 2.0 + 2.0; // slight extra padding for anti-aliasing edge cases
+        if self.tool == Tool::Arrow {
+            // The arrow head's barbs can reach past the line's own extent
+            pad += self.arrow_head_length();
+        }
         min_x -= pad;
         min_y -= pad;
         max_x += pad;
@@ -86,3 +276,145 @@ impl Stroke {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: u32, h: u32) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    fn contains(rect: &Rect, x: i32, y: i32) -> bool {
+        x >= rect.x && x < rect.x + rect.w as i32 && y >= rect.y && y < rect.y + rect.h as i32
+    }
+
+    /// Checks the region's invariants, and that it covers every pixel of `inputs`.
+    fn check(region: &Region, inputs: &[Rect]) {
+        let rects = region.rects();
+        assert!(rects.len() <= MAX_REGION_RECTS, "{} rects", rects.len());
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.w > 0 && a.h > 0, "empty rect {:?}", a);
+            for b in &rects[i + 1..] {
+                assert!(a.intersect(b).is_none(), "{:?} overlaps {:?}", a, b);
+            }
+        }
+        for input in inputs {
+            for y in input.y..input.y + input.h as i32 {
+                for x in input.x..input.x + input.w as i32 {
+                    assert!(
+                        rects.iter().any(|r| contains(r, x, y)),
+                        "({}, {}) of {:?} not covered",
+                        x,
+                        y,
+                        input
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_rects_merge() {
+        let inputs = [rect(0, 0, 10, 10), rect(5, 5, 10, 10)];
+        let mut region = Region::default();
+        for r in &inputs {
+            region.add(r.clone());
+        }
+        check(&region, &inputs);
+        assert_eq!(region.rects().len(), 1);
+    }
+
+    #[test]
+    fn distant_rects_stay_separate() {
+        let inputs = [rect(0, 0, 10, 10), rect(1000, 1000, 10, 10)];
+        let mut region = Region::default();
+        for r in &inputs {
+            region.add(r.clone());
+        }
+        check(&region, &inputs);
+        assert_eq!(region.rects().len(), 2);
+    }
+
+    #[test]
+    fn adjacent_rects_merge_when_cheap() {
+        // The union of two touching halves wastes nothing
+        let inputs = [rect(0, 0, 10, 10), rect(10, 0, 10, 10)];
+        let mut region = Region::default();
+        for r in &inputs {
+            region.add(r.clone());
+        }
+        check(&region, &inputs);
+        assert_eq!(region.rects().len(), 1);
+    }
+
+    #[test]
+    fn merges_cascade_to_keep_rects_disjoint() {
+        // The last rect bridges the first two, whose merge must absorb both
+        let inputs = [rect(0, 0, 10, 10), rect(30, 0, 10, 10), rect(5, 0, 30, 5)];
+        let mut region = Region::default();
+        for r in &inputs {
+            region.add(r.clone());
+        }
+        check(&region, &inputs);
+    }
+
+    #[test]
+    fn empty_rects_are_ignored() {
+        let mut region = Region::default();
+        region.add(rect(5, 5, 0, 10));
+        region.add(rect(5, 5, 10, 0));
+        assert!(region.is_empty());
+    }
+
+    #[test]
+    fn scattered_rects_stay_under_the_cap() {
+        // A deterministic spread of small rects across a 4K screen
+        let mut seed = 12345u32;
+        let mut next = |max: u32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) % max
+        };
+        let mut region = Region::default();
+        let mut inputs = Vec::new();
+        for _ in 0..40 {
+            let r = rect(
+                next(3800) as i32,
+                next(2100) as i32,
+                1 + next(40),
+                1 + next(40),
+            );
+            region.add(r.clone());
+            inputs.push(r);
+            check(&region, &inputs);
+        }
+        assert_eq!(region.rects().len(), MAX_REGION_RECTS);
+    }
+
+    #[test]
+    fn add_region_and_clip() {
+        let mut a = Region::default();
+        a.add(rect(0, 0, 10, 10));
+        let mut b = Region::default();
+        b.add(rect(100, 100, 10, 10));
+        b.add(rect(5, 5, 10, 10));
+        a.add_region(&b);
+        check(
+            &a,
+            &[
+                rect(0, 0, 10, 10),
+                rect(100, 100, 10, 10),
+                rect(5, 5, 10, 10),
+            ],
+        );
+
+        let clipped = a.clip(&rect(0, 0, 50, 50));
+        check(&clipped, &[rect(0, 0, 15, 15)]);
+        assert!(clipped
+            .rects()
+            .iter()
+            .all(|r| r.x >= 0 && r.y >= 0 && r.x + r.w as i32 <= 50 && r.y + r.h as i32 <= 50));
+        assert!(clipped.intersects(&rect(12, 12, 1, 1)));
+        assert!(!clipped.intersects(&rect(40, 40, 5, 5)));
+    }
+}